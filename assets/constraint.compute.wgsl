@@ -0,0 +1,86 @@
+#import logic_particles::constants
+#import logic_particles::particle
+
+@group(0) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(1)
+var<uniform> simulation: SimulationUniform;
+// A single batch, no particle appears in it twice
+@group(0) @binding(2)
+var<storage, read> links: array<ConstraintData>;
+@group(0) @binding(3)
+var<storage, read> points: array<ConstraintPointData>;
+@group(0) @binding(4)
+var<uniform> emitter: EmitterUniform;
+
+// Lifetime of the points, the boundary doesn't kill them either
+let FOREVER: f32 = 1e30;
+let VERLET: u32 = 2u;
+
+// How much of a correction a particle takes on, pinned particles don't give way
+fn weight(particle: Particle) -> f32 {
+    if (particle.pinned != 0u) {
+        return 0.0;
+    }
+    return 1.0 / max(particle.mass, 1e-6);
+}
+
+// Fills the slots init kept off the dead list with the constraint points, at rest
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn place(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.constraint_points) {
+        return;
+    }
+
+    let placed = points[id];
+    let position = (emitter.transform * vec4<f32>(emitter.origin + placed.position, 0.0, 1.0)).xy;
+    var particle: Particle;
+    particle.position = position;
+    particle.previous_position = position;
+    particle.size = emitter.size;
+    particle.color = emitter.color;
+    particle.lifetime = FOREVER;
+    particle.mass = emitter.mass.x;
+    particle.pinned = placed.pinned;
+    particles[id] = particle;
+}
+
+// Moves both ends of a link towards its rest length, split by their weights
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn solve(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= arrayLength(&links)) {
+        return;
+    }
+
+    let link = links[id];
+    if (link.a == link.b) {
+        return;
+    }
+    var a = particles[link.a];
+    var b = particles[link.b];
+    if (!is_alive(a) || !is_alive(b)) {
+        return;
+    }
+
+    let weight_a = weight(a);
+    let weight_b = weight(b);
+    let offset = b.position - a.position;
+    let distance = length(offset);
+    if (weight_a + weight_b == 0.0 || distance == 0.0) {
+        return;
+    }
+
+    let correction = link.stiffness * (distance - link.rest_length) / (distance * (weight_a + weight_b)) * offset;
+    a.position += weight_a * correction;
+    b.position -= weight_b * correction;
+    // verlet picks the correction up through the previous position, the others need it
+    // in their velocity
+    if (simulation.integrator != VERLET) {
+        a.velocity += weight_a * correction / simulation.delta_time;
+        b.velocity -= weight_b * correction / simulation.delta_time;
+    }
+    particles[link.a] = a;
+    particles[link.b] = b;
+}
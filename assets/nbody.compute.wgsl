@@ -0,0 +1,60 @@
+#import logic_particles::constants
+#import logic_particles::particle
+
+@group(0) @binding(0)
+var<storage, read> particles: array<Particle>;
+@group(0) @binding(1)
+var<uniform> simulation: SimulationUniform;
+@group(0) @binding(2)
+var<storage, read_write> interaction_forces: array<vec2<f32>>;
+
+// Positions and masses of the bodies the workgroup is currently summing up
+var<workgroup> tile: array<vec3<f32>, #{TILE_SIZE}>;
+
+// Softened gravity from every particle, each thread loads one body of a tile into shared
+// memory and then the whole workgroup sums up that tile, so every body is read once per
+// workgroup instead of once per particle
+@compute @workgroup_size(#{TILE_SIZE}, 1, 1)
+fn gravity(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(local_invocation_index) thread: u32, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = tiled_id(invocation_id, num_workgroups);
+    // threads past the end still help load tiles, all of them have to reach the barriers
+    var position = vec2<f32>(0.0);
+    if (id < simulation.count) {
+        position = particles[id].position;
+    }
+    let softening = simulation.softening * simulation.softening;
+
+    var acceleration = vec2<f32>(0.0);
+    for (var start = 0u; start < simulation.count; start += TILE_SIZE) {
+        // dead and missing bodies weigh nothing
+        var body = vec3<f32>(0.0);
+        let index = start + thread;
+        if (index < simulation.count) {
+            let particle = particles[index];
+            if (is_alive(particle)) {
+                body = vec3<f32>(particle.position, particle.mass);
+            }
+        }
+        tile[thread] = body;
+        workgroupBarrier();
+
+        for (var i = 0u; i < TILE_SIZE; i += 1u) {
+            let offset = tile[i].xy - position;
+            // a particle's own offset is zero, the max only keeps it from dividing by zero
+            let distance_squared = max(dot(offset, offset) + softening, 1e-6);
+            let inverse_distance = inverseSqrt(distance_squared);
+            acceleration += tile[i].z * offset * inverse_distance * inverse_distance * inverse_distance;
+        }
+        workgroupBarrier();
+    }
+
+    if (id >= simulation.count) {
+        return;
+    }
+    acceleration *= simulation.gravitational_constant;
+    // the grid already wrote the forces from neighbors this step
+    if (simulation.grid_size.x > 0u) {
+        acceleration += interaction_forces[id];
+    }
+    interaction_forces[id] = acceleration;
+}
@@ -0,0 +1,93 @@
+#import logic_particles::constants
+#import logic_particles::particle
+
+@group(0) @binding(0)
+var sdf: texture_storage_2d<r32float, write>;
+@group(0) @binding(1)
+var<storage, read> obstacles: ObstacleShapes;
+@group(0) @binding(2)
+var mask: texture_2d<f32>;
+
+// How far around a pixel the mask is searched for an edge
+let MASK_RADIUS: i32 = 3;
+
+fn circle_distance(p: vec2<f32>, radius: f32) -> f32 {
+    return length(p) - radius;
+}
+
+fn box_distance(p: vec2<f32>, half_extents: vec2<f32>) -> f32 {
+    let d = abs(p) - half_extents;
+    return length(max(d, vec2<f32>(0.0))) + min(max(d.x, d.y), 0.0);
+}
+
+fn capsule_distance(p: vec2<f32>, start: vec2<f32>, end: vec2<f32>, radius: f32) -> f32 {
+    let pa = p - start;
+    let ba = end - start;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 0.0001), 0.0, 1.0);
+    return length(pa - ba * h) - radius;
+}
+
+// Following the ids in `ObstacleShape::data`
+fn shape_distance(p: vec2<f32>, shape: ObstacleShapeData) -> f32 {
+    switch (shape.kind) {
+        // box
+        case 1u: {
+            return box_distance(p - shape.a, shape.b);
+        }
+        // capsule
+        case 2u: {
+            return capsule_distance(p, shape.a, shape.b, shape.radius);
+        }
+        // circle
+        default: {
+            return circle_distance(p - shape.a, shape.radius);
+        }
+    }
+}
+
+fn is_solid(pixel: vec2<i32>) -> bool {
+    let size = vec2<i32>(textureDimensions(mask));
+    if (any(pixel < vec2<i32>(0)) || any(pixel >= size)) {
+        return false;
+    }
+    return textureLoad(mask, pixel, 0).a > 0.5;
+}
+
+// Signed distance to the closest edge of the mask, only exact up to MASK_RADIUS
+fn mask_distance(pixel: vec2<i32>) -> f32 {
+    let solid = is_solid(pixel);
+    var closest = f32(MASK_RADIUS) + 1.0;
+    for (var y = -MASK_RADIUS; y <= MASK_RADIUS; y += 1) {
+        for (var x = -MASK_RADIUS; x <= MASK_RADIUS; x += 1) {
+            if (is_solid(pixel + vec2<i32>(x, y)) != solid) {
+                closest = min(closest, length(vec2<f32>(f32(x), f32(y))));
+            }
+        }
+    }
+    // the edge lies halfway between two pixels
+    let distance = closest - 0.5;
+    return select(distance, -distance, solid);
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, #{WORKGROUP_SIZE}, 1)
+fn bake(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (f32(invocation_id.x) >= WIDTH || f32(invocation_id.y) >= HEIGHT) {
+        return;
+    }
+
+    let pixel = vec2<i32>(invocation_id.xy);
+    let p = vec2<f32>(pixel) + 0.5 - vec2<f32>(WIDTH, HEIGHT) * 0.5;
+
+    var distance = WIDTH + HEIGHT;
+    for (var i = 0u; i < obstacles.count; i += 1u) {
+        distance = min(distance, shape_distance(p, obstacles.shapes[i]));
+    }
+
+    if (obstacles.use_mask != 0u) {
+        // the mask is centered on the texture
+        let offset = (vec2<i32>(textureDimensions(mask)) - vec2<i32>(i32(WIDTH), i32(HEIGHT))) / 2;
+        distance = min(distance, mask_distance(pixel + offset));
+    }
+
+    textureStore(sdf, pixel, vec4<f32>(distance, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,90 @@
+#import bevy_render::view
+#import logic_particles::particle
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+@group(1) @binding(0)
+var<storage, read> particles: array<Particle>;
+@group(1) @binding(1)
+var<uniform> simulation: SimulationUniform;
+
+#ifdef FLIPBOOK
+@group(2) @binding(0)
+var sheet: texture_2d<f32>;
+@group(2) @binding(1)
+var sheet_sampler: sampler;
+// Uv rect of every frame, min then max
+@group(2) @binding(2)
+var<storage, read> frames: array<vec4<f32>>;
+
+let ONCE = 1u;
+
+// The frame a particle shows at its age
+fn frame(particle: Particle) -> u32 {
+    let count = arrayLength(&frames);
+    var start = 0.0;
+    if (simulation.random_start_frame != 0u) {
+        start = floor(particle.frame_offset * f32(count));
+    }
+    let played = u32(start + particle.age * simulation.frame_rate);
+    if (simulation.playback == ONCE) {
+        return min(played, count - 1u);
+    }
+    return played % count;
+}
+#endif
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+// One quad per instance, pulled straight from the particle buffer without a vertex buffer
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let particle = particles[instance_index];
+    if (!is_alive(particle)) {
+        // every corner in the same spot, so nothing is rasterized
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+        return out;
+    }
+
+    // two triangles over the corners of a unit square
+    var corners = array<u32, 6>(0u, 1u, 2u, 2u, 1u, 3u);
+    let corner = corners[vertex_index];
+    out.uv = vec2<f32>(f32(corner & 1u), f32(corner >> 1u));
+
+    let position = extrapolate(particle, simulation.alpha * simulation.delta_time);
+    let center = (simulation.simulation_to_texture * vec4<f32>(position, 0.0, 1.0)).xy;
+    // as wide as the plus sign the texture mode plots
+    let offset = (out.uv - 0.5) * (2.0 * max(particle.size, 1.0) + 1.0);
+    let c = cos(particle.rotation);
+    let s = sin(particle.rotation);
+    let pixel = center + vec2<f32>(c * offset.x - s * offset.y, s * offset.x + c * offset.y);
+
+    let local = simulation.texture_to_simulation * vec4<f32>(pixel, 0.0, 1.0);
+    out.clip_position = view.view_proj * simulation.simulation_to_world * local;
+    out.color = particle.color;
+#ifdef FLIPBOOK
+    let rect = frames[frame(particle)];
+    out.uv = mix(rect.xy, rect.zw, out.uv);
+#endif
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = in.color;
+#ifdef FLIPBOOK
+    color *= textureSample(sheet, sheet_sampler, in.uv);
+#endif
+#ifdef PREMULTIPLIED
+    return color;
+#else
+    // every blend mode works on premultiplied colors
+    return vec4<f32>(color.rgb * color.a, color.a);
+#endif
+}
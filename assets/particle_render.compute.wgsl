@@ -0,0 +1,166 @@
+#import logic_particles::constants
+#import logic_particles::particle
+
+@group(0) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(1)
+var texture: texture_storage_2d<rgba8unorm, read_write>;
+@group(0) @binding(2)
+var<uniform> simulation: SimulationUniform;
+// Where `diffuse` blurs the trail into, it is copied back before particles are drawn
+@group(0) @binding(3)
+var trail_scratch: texture_storage_2d<rgba8unorm, write>;
+// Fixed point sums of the particles over each pixel, color then weight, see `blend_sample`
+@group(0) @binding(4)
+var<storage, read_write> accumulation: array<atomic<u32>>;
+
+let ADDITIVE = 1u;
+let PREMULTIPLIED = 2u;
+let MULTIPLY = 3u;
+let SCREEN = 4u;
+let ACCUMULATION_SCALE = 1024.0;
+
+// What a particle adds to its pixels. Sums don't depend on the order particles are drawn in, so
+// the over-like modes sum optical depths and `resolve` turns them back into colors
+fn blend_sample(color: vec4<f32>) -> vec4<f32> {
+    var straight = clamp(color, vec4<f32>(0.0), vec4<f32>(1.0));
+    if (simulation.blend_mode == PREMULTIPLIED && straight.a > 0.0) {
+        straight = vec4<f32>(min(straight.rgb / straight.a, vec3<f32>(1.0)), straight.a);
+    }
+    let alpha = min(straight.a, 0.999);
+    let depth = -log(1.0 - alpha);
+
+    if (simulation.blend_mode == ADDITIVE) {
+        return vec4<f32>(straight.rgb * alpha, alpha);
+    }
+    if (simulation.blend_mode == MULTIPLY) {
+        let tint = mix(vec3<f32>(1.0), straight.rgb, alpha);
+        return vec4<f32>(-log(max(tint, vec3<f32>(0.001))), depth);
+    }
+    if (simulation.blend_mode == SCREEN) {
+        return vec4<f32>(-log(max(1.0 - straight.rgb * alpha, vec3<f32>(0.001))), depth);
+    }
+    return vec4<f32>(straight.rgb * depth, depth);
+}
+
+fn accumulation_index(pixel: vec2<i32>) -> u32 {
+    return (u32(pixel.y) * u32(WIDTH) + u32(pixel.x)) * 4u;
+}
+
+fn plot(pixel: vec2<i32>, color: vec4<f32>) {
+    if (pixel.x < 0 || pixel.y < 0 || f32(pixel.x) >= WIDTH || f32(pixel.y) >= HEIGHT) {
+        return;
+    }
+#ifdef PHYSARUM
+    // agents add to the trail instead of drawing over it
+    textureStore(texture, pixel, min(textureLoad(texture, pixel) + color * simulation.deposit, vec4<f32>(1.0)));
+#else
+    let index = accumulation_index(pixel);
+    let sample = vec4<u32>(blend_sample(color) * ACCUMULATION_SCALE + 0.5);
+    atomicAdd(&accumulation[index], sample.r);
+    atomicAdd(&accumulation[index + 1u], sample.g);
+    atomicAdd(&accumulation[index + 2u], sample.b);
+    atomicAdd(&accumulation[index + 3u], sample.a);
+#endif
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, #{WORKGROUP_SIZE}, 1)
+fn clear(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    if (f32(invocation_id.x) >= WIDTH || f32(invocation_id.y) >= HEIGHT) {
+        return;
+    }
+
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    textureStore(texture, location, vec4<f32>(0.0,0.0,0.0,0.0));
+    let index = accumulation_index(location);
+    atomicStore(&accumulation[index], 0u);
+    atomicStore(&accumulation[index + 1u], 0u);
+    atomicStore(&accumulation[index + 2u], 0u);
+    atomicStore(&accumulation[index + 3u], 0u);
+}
+
+// Turns the sums `render` made into the straight alpha color of each pixel
+@compute @workgroup_size(#{WORKGROUP_SIZE}, #{WORKGROUP_SIZE}, 1)
+fn resolve(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (f32(invocation_id.x) >= WIDTH || f32(invocation_id.y) >= HEIGHT) {
+        return;
+    }
+
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    let index = accumulation_index(location);
+    let sum = vec4<f32>(
+        f32(atomicLoad(&accumulation[index])),
+        f32(atomicLoad(&accumulation[index + 1u])),
+        f32(atomicLoad(&accumulation[index + 2u])),
+        f32(atomicLoad(&accumulation[index + 3u])),
+    ) / ACCUMULATION_SCALE;
+    // nothing was drawn here, `clear` already left it transparent
+    if (sum.a <= 0.0) {
+        return;
+    }
+
+    var alpha = 1.0 - exp(-sum.a);
+    var color = sum.rgb / sum.a;
+    if (simulation.blend_mode == ADDITIVE) {
+        let added = min(sum.rgb, vec3<f32>(1.0));
+        alpha = min(max(sum.a, max(added.r, max(added.g, added.b))), 1.0);
+        color = added / alpha;
+    } else if (simulation.blend_mode == MULTIPLY) {
+        color = exp(-sum.rgb);
+    } else if (simulation.blend_mode == SCREEN) {
+        color = min((1.0 - exp(-sum.rgb)) / alpha, vec3<f32>(1.0));
+    }
+    textureStore(texture, location, vec4<f32>(color, alpha));
+}
+
+// Blurs and fades the trail of physarum systems by one simulation step, replacing `clear`
+@compute @workgroup_size(#{WORKGROUP_SIZE}, #{WORKGROUP_SIZE}, 1)
+fn diffuse(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (f32(invocation_id.x) >= WIDTH || f32(invocation_id.y) >= HEIGHT) {
+        return;
+    }
+
+    let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
+    let last = vec2<i32>(i32(WIDTH) - 1, i32(HEIGHT) - 1);
+    var sum = vec4<f32>(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            sum += textureLoad(texture, clamp(location + vec2<i32>(x, y), vec2<i32>(0), last));
+        }
+    }
+
+    let blurred = sum / 9.0;
+    let fade = simulation.decay * simulation.delta_time;
+    var faded = blurred * fade;
+    // less than one step of the 8 bit texture rounds back to the same value, and the trail
+    // would never fade out completely
+    if (fade > 0.0) {
+        faded = max(faded, vec4<f32>(1.0 / 255.0));
+    }
+    textureStore(trail_scratch, location, max(blurred - faded, vec4<f32>(0.0)));
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn render(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        return;
+    }
+    let position = extrapolate(particle, simulation.alpha * simulation.delta_time);
+    // floor so pixels just left of or above the texture don't round onto its edge
+    let center = vec2<i32>(floor((simulation.simulation_to_texture * vec4<f32>(position, 0.0, 1.0)).xy));
+    plot(center, particle.color);
+
+    let arm = max(i32(particle.size), 1);
+    for (var i = 1; i <= arm; i += 1) {
+        plot(center + vec2<i32>(0, i), particle.color);
+        plot(center + vec2<i32>(0, -i), particle.color);
+        plot(center + vec2<i32>(i, 0), particle.color);
+        plot(center + vec2<i32>(-i, 0), particle.color);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,577 @@
+#import logic_particles::constants
+#import logic_particles::particle
+
+// The state being written, init and emit work on it in place
+@group(0) @binding(0)
+var<storage, read_write> particles: array<Particle>;
+@group(0) @binding(1)
+var<uniform> simulation: SimulationUniform;
+
+// Slots of dead particles, pushed by update and popped by emit
+struct DeadList {
+    count: atomic<u32>,
+    indices: array<u32>,
+}
+
+@group(0) @binding(2)
+var<storage, read_write> dead_list: DeadList;
+@group(0) @binding(3)
+var<uniform> emitter: EmitterUniform;
+@group(0) @binding(4)
+var spawn_mask: texture_2d<f32>;
+@group(0) @binding(5)
+var<storage, read> force_fields: ForceFields;
+@group(0) @binding(6)
+var obstacle_sdf: texture_2d<f32>;
+// Forces between neighbors summed up by the spatial grid, zero without interaction
+@group(0) @binding(7)
+var<storage, read> interaction_forces: array<vec2<f32>>;
+// The rendered texture, physarum agents follow the trail left on it
+@group(0) @binding(8)
+var trail: texture_2d<f32>;
+// The state update steps on from, the other of the two particle buffers
+@group(0) @binding(9)
+var<storage, read> previous_particles: array<Particle>;
+
+let PI: f32 = 3.14159265;
+let MASK_TRIES: u32 = 16u;
+// See `Integrator::id`
+let EXPLICIT_EULER: u32 = 0u;
+let VERLET: u32 = 2u;
+
+fn hash(value: u32) -> u32 {
+    var state = value;
+    state = state ^ 2747636419u;
+    state = state * 2654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2654435769u;
+    return state;
+}
+
+fn randomFloat(value: u32) -> f32 {
+    return f32(hash(value)) / 4294967295.0;
+}
+
+fn hash2(value: u32) -> u32 {
+    var state = value;
+    state = state ^ 3104587013u;
+    state = state * 1654435769u;
+    state = state ^ state >> 16u;
+    state = state * 2301324115u;
+    state = state ^ state >> 16u;
+    state = state * 2351435769u;
+    return state;
+}
+
+fn randomFloat2(value: u32) -> f32 {
+    return f32(hash2(value)) / 4294967295.0;
+}
+
+// Simplex noise after Ashima Arts' webgl-noise, values are roughly in [-1, 1]
+fn mod289_2(x: vec2<f32>) -> vec2<f32> {
+    return x - floor(x * (1.0 / 289.0)) * 289.0;
+}
+
+fn mod289_3(x: vec3<f32>) -> vec3<f32> {
+    return x - floor(x * (1.0 / 289.0)) * 289.0;
+}
+
+fn mod289_4(x: vec4<f32>) -> vec4<f32> {
+    return x - floor(x * (1.0 / 289.0)) * 289.0;
+}
+
+fn permute3(x: vec3<f32>) -> vec3<f32> {
+    return mod289_3((x * 34.0 + 1.0) * x);
+}
+
+fn permute4(x: vec4<f32>) -> vec4<f32> {
+    return mod289_4((x * 34.0 + 1.0) * x);
+}
+
+fn simplex_noise_2d(v: vec2<f32>) -> f32 {
+    let C = vec4<f32>(0.211324865405187, 0.366025403784439, -0.577350269189626, 0.024390243902439);
+
+    // first corner
+    var i = floor(v + dot(v, C.yy));
+    let x0 = v - i + dot(i, C.xx);
+
+    // other corners
+    var i1 = vec2<f32>(0.0, 1.0);
+    if (x0.x > x0.y) {
+        i1 = vec2<f32>(1.0, 0.0);
+    }
+    let x12 = x0.xyxy + C.xxzz - vec4<f32>(i1, 0.0, 0.0);
+
+    i = mod289_2(i);
+    let p = permute3(permute3(i.y + vec3<f32>(0.0, i1.y, 1.0)) + i.x + vec3<f32>(0.0, i1.x, 1.0));
+
+    var m = max(0.5 - vec3<f32>(dot(x0, x0), dot(x12.xy, x12.xy), dot(x12.zw, x12.zw)), vec3<f32>(0.0));
+    m = m * m;
+    m = m * m;
+
+    // gradients from 41 points on a line mapped onto a diamond
+    let x = 2.0 * fract(p * C.www) - 1.0;
+    let h = abs(x) - 0.5;
+    let a0 = x - floor(x + 0.5);
+    m = m * (1.79284291400159 - 0.85373472095314 * (a0 * a0 + h * h));
+
+    let g = vec3<f32>(a0.x * x0.x + h.x * x0.y, a0.yz * x12.xz + h.yz * x12.yw);
+    return 130.0 * dot(m, g);
+}
+
+fn simplex_noise_3d(v: vec3<f32>) -> f32 {
+    let C = vec2<f32>(1.0 / 6.0, 1.0 / 3.0);
+    let D = vec4<f32>(0.0, 0.5, 1.0, 2.0);
+
+    // first corner
+    var i = floor(v + dot(v, C.yyy));
+    let x0 = v - i + dot(i, C.xxx);
+
+    // other corners
+    let g = step(x0.yzx, x0.xyz);
+    let l = 1.0 - g;
+    let i1 = min(g.xyz, l.zxy);
+    let i2 = max(g.xyz, l.zxy);
+    let x1 = x0 - i1 + C.xxx;
+    let x2 = x0 - i2 + C.yyy;
+    let x3 = x0 - D.yyy;
+
+    i = mod289_3(i);
+    let p = permute4(permute4(permute4(
+        i.z + vec4<f32>(0.0, i1.z, i2.z, 1.0))
+        + i.y + vec4<f32>(0.0, i1.y, i2.y, 1.0))
+        + i.x + vec4<f32>(0.0, i1.x, i2.x, 1.0));
+
+    // gradients from a 7x7 grid on a square mapped onto an octahedron
+    let ns = 0.142857142857 * D.wyz - D.xzx;
+    let j = p - 49.0 * floor(p * ns.z * ns.z);
+    let x_ = floor(j * ns.z);
+    let y_ = floor(j - 7.0 * x_);
+    let x = x_ * ns.x + ns.yyyy;
+    let y = y_ * ns.x + ns.yyyy;
+    let h = 1.0 - abs(x) - abs(y);
+
+    let b0 = vec4<f32>(x.xy, y.xy);
+    let b1 = vec4<f32>(x.zw, y.zw);
+    let s0 = floor(b0) * 2.0 + 1.0;
+    let s1 = floor(b1) * 2.0 + 1.0;
+    let sh = -step(h, vec4<f32>(0.0));
+    let a0 = b0.xzyw + s0.xzyw * sh.xxyy;
+    let a1 = b1.xzyw + s1.xzyw * sh.zzww;
+
+    let g0 = vec3<f32>(a0.xy, h.x);
+    let g1 = vec3<f32>(a0.zw, h.y);
+    let g2 = vec3<f32>(a1.xy, h.z);
+    let g3 = vec3<f32>(a1.zw, h.w);
+    let norm = 1.79284291400159 - 0.85373472095314 * vec4<f32>(dot(g0, g0), dot(g1, g1), dot(g2, g2), dot(g3, g3));
+
+    var m = max(0.6 - vec4<f32>(dot(x0, x0), dot(x1, x1), dot(x2, x2), dot(x3, x3)), vec4<f32>(0.0));
+    m = m * m;
+    return 42.0 * dot(m * m, norm * vec4<f32>(dot(g0, x0), dot(g1, x1), dot(g2, x2), dot(g3, x3)));
+}
+
+// Octaves of noise, the third coordinate is only sampled when it changes over time
+fn fractal_noise(p: vec3<f32>, octaves: u32, evolving: bool) -> f32 {
+    var value = 0.0;
+    var amplitude = 0.5;
+    var frequency = 1.0;
+    for (var i = 0u; i < octaves; i += 1u) {
+        if (evolving) {
+            value += amplitude * simplex_noise_3d(p * frequency);
+        } else {
+            value += amplitude * simplex_noise_2d(p.xy * frequency);
+        }
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    return value;
+}
+
+// Divergence free flow from the curl of the noise, used as a stream function
+fn curl_noise(p: vec3<f32>, octaves: u32, evolving: bool) -> vec2<f32> {
+    let e = 0.01;
+    let dx = fractal_noise(p + vec3<f32>(e, 0.0, 0.0), octaves, evolving)
+        - fractal_noise(p - vec3<f32>(e, 0.0, 0.0), octaves, evolving);
+    let dy = fractal_noise(p + vec3<f32>(0.0, e, 0.0), octaves, evolving)
+        - fractal_noise(p - vec3<f32>(0.0, e, 0.0), octaves, evolving);
+    return vec2<f32>(dy, -dx) / (2.0 * e);
+}
+
+// Offset from the emitter origin in xy, following the shape ids in `EmitterShape::id`.
+// z is 0 when there is nowhere to spawn
+fn spawn_offset(seed: u32) -> vec3<f32> {
+    let a = randomFloat(seed);
+    let b = randomFloat2(seed);
+    switch (emitter.shape) {
+        // circle
+        case 1u: {
+            let angle = 2.0 * PI * b;
+            return vec3<f32>(emitter.shape_a.x * sqrt(a) * vec2<f32>(cos(angle), sin(angle)), 1.0);
+        }
+        // ring
+        case 2u: {
+            let inner = emitter.shape_a.x * emitter.shape_a.x;
+            let outer = emitter.shape_a.y * emitter.shape_a.y;
+            let angle = 2.0 * PI * b;
+            return vec3<f32>(sqrt(mix(inner, outer, a)) * vec2<f32>(cos(angle), sin(angle)), 1.0);
+        }
+        // rectangle
+        case 3u: {
+            return vec3<f32>((vec2<f32>(a, b) * 2.0 - 1.0) * emitter.shape_a, 1.0);
+        }
+        // line
+        case 4u: {
+            return vec3<f32>(mix(emitter.shape_a, emitter.shape_b, a), 1.0);
+        }
+        // mask, keeps the brightest of a few random pixels, none if they are all dark
+        case 5u: {
+            let size = vec2<f32>(textureDimensions(spawn_mask));
+            var best = vec2<f32>(0.0);
+            var best_weight = -1.0;
+            for (var i = 0u; i < MASK_TRIES; i += 1u) {
+                let pixel = vec2<f32>(randomFloat(seed + i), randomFloat2(seed + i)) * size;
+                let texel = textureLoad(spawn_mask, vec2<i32>(pixel), 0);
+                let weight = texel.a * max(texel.r, max(texel.g, texel.b));
+                if (weight > best_weight) {
+                    best = pixel;
+                    best_weight = weight;
+                }
+            }
+            return vec3<f32>(best - size * 0.5, f32(best_weight > 0.0));
+        }
+        // point
+        default: {
+            return vec3<f32>(0.0, 0.0, 1.0);
+        }
+    }
+}
+
+// Every particle starts out dead, so the whole buffer is up for grabs by emit, except for the
+// first slots the constraint points are placed in
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+    if (id == 0u) {
+        atomicStore(&dead_list.count, simulation.count - simulation.constraint_points);
+    }
+
+    var particle: Particle;
+    particles[id] = particle;
+    if (id >= simulation.constraint_points) {
+        dead_list.indices[id - simulation.constraint_points] = id;
+    }
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn emit(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.emit_count) {
+        return;
+    }
+
+    let seed = hash(id ^ hash(simulation.seed ^ hash(simulation.frame)));
+    // picked before a slot is taken, so a spawn that finds no place doesn't use one up
+    let offset = spawn_offset(seed);
+    if (offset.z == 0.0) {
+        return;
+    }
+
+    // constraint points never die, only the slots after them count towards `max_alive`.
+    // an underflowed count wraps around past them, hand the slot back in all cases
+    let slots = simulation.count - simulation.constraint_points;
+    let dead = atomicSub(&dead_list.count, 1u);
+    if (dead == 0u || dead > slots || slots - dead >= emitter.max_alive) {
+        atomicAdd(&dead_list.count, 1u);
+        return;
+    }
+    let index = dead_list.indices[dead - 1u];
+
+    let local = emitter.origin + offset.xy;
+    let location = (emitter.transform * vec4<f32>(local, 0.0, 1.0)).xy;
+
+    let angle = atan2(emitter.direction.y, emitter.direction.x) + emitter.spread * (2.0 * randomFloat(seed + 1u) - 1.0);
+    let speed = mix(emitter.speed.x, emitter.speed.y, randomFloat2(seed + 1u));
+    let velocity = (emitter.transform * vec4<f32>(speed * vec2<f32>(cos(angle), sin(angle)), 0.0, 0.0)).xy;
+    // a particle born dead would never reach the update that hands its slot back
+    let lifetime = max(mix(emitter.lifetime.x, emitter.lifetime.y, randomFloat(seed + 2u)), 1e-6);
+
+    particles[index] = Particle(
+        location,
+        velocity,
+        emitter.acceleration,
+        emitter.size,
+        0.0,
+        emitter.color,
+        0.0,
+        lifetime,
+        mix(emitter.mass.x, emitter.mass.y, randomFloat2(seed + 2u)),
+        location - velocity * simulation.delta_time,
+        0u,
+        randomFloat(seed + 3u),
+    );
+}
+
+// Acceleration of all force fields on a particle, fields are given in world space
+fn force_field_acceleration(particle: Particle) -> vec2<f32> {
+    // simulation units per world unit, assumes the transform scales uniformly
+    let scale = length(simulation.world_to_simulation * vec4<f32>(1.0, 0.0, 0.0, 0.0));
+    let speed = length(particle.velocity);
+    // counterclockwise in world space, which the flip into canvas space turns around
+    let m = simulation.world_to_simulation;
+    let winding = sign(m[0].x * m[1].y - m[0].y * m[1].x);
+
+    var acceleration = vec2<f32>(0.0);
+    for (var i = 0u; i < force_fields.count; i += 1u) {
+        let field = force_fields.fields[i];
+        let force = (simulation.world_to_simulation * vec4<f32>(field.force, 0.0, 0.0)).xy;
+
+        switch (field.kind) {
+            // gravity
+            case 0u: {
+                acceleration += force;
+            }
+            // drag, the quadratic term is per world unit of speed
+            case 1u: {
+                let drag = field.force.x + field.force.y * speed / scale;
+                acceleration -= particle.velocity * drag;
+            }
+            // wind
+            case 2u: {
+                acceleration += (force - particle.velocity) * field.strength;
+            }
+            // attractor or repulsor
+            case 3u: {
+                let position = (simulation.world_to_simulation * vec4<f32>(field.position, 0.0, 1.0)).xy;
+                let offset = position - particle.position;
+                let distance = length(offset);
+                let radius = field.radius * scale;
+                if (distance > 0.0 && distance < radius) {
+                    let falloff = pow(1.0 - distance / radius, field.falloff);
+                    acceleration += offset / distance * field.strength * scale * falloff;
+                }
+            }
+            // turbulence
+            case 4u: {
+                let world_position = (simulation.simulation_to_world * vec4<f32>(particle.position, 0.0, 1.0)).xy;
+                let p = vec3<f32>(
+                    (world_position - field.force * simulation.elapsed_time) * field.frequency,
+                    field.evolution * simulation.elapsed_time,
+                );
+                let curl = curl_noise(p, max(field.octaves, 1u), field.evolution != 0.0);
+                acceleration += (simulation.world_to_simulation * vec4<f32>(curl * field.strength, 0.0, 0.0)).xy;
+            }
+            // vortex
+            case 5u: {
+                let position = (simulation.world_to_simulation * vec4<f32>(field.position, 0.0, 1.0)).xy;
+                let offset = position - particle.position;
+                let distance = length(offset);
+                let radius = field.radius * scale;
+                if (distance > 0.0 && distance < radius) {
+                    let inward = offset / distance;
+                    let tangent = vec2<f32>(inward.y, -inward.x) * winding;
+                    let falloff = pow(1.0 - distance / radius, field.falloff);
+                    acceleration += (tangent * field.strength + inward * field.force.x) * scale * falloff;
+                }
+            }
+            // orbit, steers towards the velocity of a particle on the circle
+            case 6u: {
+                let position = (simulation.world_to_simulation * vec4<f32>(field.position, 0.0, 1.0)).xy;
+                let offset = position - particle.position;
+                let distance = length(offset);
+                if (distance > 0.0) {
+                    let inward = offset / distance;
+                    let tangent = vec2<f32>(inward.y, -inward.x) * winding;
+                    let radius = field.radius * scale;
+                    let target_velocity = tangent * field.force.x * scale + inward * (distance - radius);
+                    acceleration += (target_velocity - particle.velocity) * field.strength;
+                }
+            }
+            default: {}
+        }
+    }
+    return acceleration;
+}
+
+fn obstacle_distance(position: vec2<f32>) -> f32 {
+    let last = vec2<i32>(i32(WIDTH) - 1, i32(HEIGHT) - 1);
+    return textureLoad(obstacle_sdf, clamp(vec2<i32>(floor(position)), vec2<i32>(0), last), 0).r;
+}
+
+// Pushes particles out of obstacles and reflects the velocity into them off the surface
+fn collide_with_obstacles(particle: Particle) -> Particle {
+    var result = particle;
+    var position = (simulation.simulation_to_texture * vec4<f32>(particle.position, 0.0, 1.0)).xy;
+    let distance = obstacle_distance(position);
+    if (distance >= 0.0) {
+        return result;
+    }
+
+    let gradient = vec2<f32>(
+        obstacle_distance(position + vec2<f32>(1.0, 0.0)) - obstacle_distance(position - vec2<f32>(1.0, 0.0)),
+        obstacle_distance(position + vec2<f32>(0.0, 1.0)) - obstacle_distance(position - vec2<f32>(0.0, 1.0)),
+    );
+    // deep inside a mask the distance is flat and there is no way out
+    if (all(gradient == vec2<f32>(0.0))) {
+        return result;
+    }
+    let normal = normalize(gradient);
+    position += normal * (0.5 - distance);
+
+    var velocity = (simulation.simulation_to_texture * vec4<f32>(particle.velocity, 0.0, 0.0)).xy;
+    let into = dot(velocity, normal);
+    if (into < 0.0) {
+        let tangent = velocity - normal * into;
+        velocity = tangent * (1.0 - simulation.obstacle_friction) - normal * into * simulation.obstacle_restitution;
+    }
+
+    result.position = (simulation.texture_to_simulation * vec4<f32>(position, 0.0, 1.0)).xy;
+    result.velocity = (simulation.texture_to_simulation * vec4<f32>(velocity, 0.0, 0.0)).xy;
+    return result;
+}
+
+// Keeps particles on the rendered texture, following the ids in `Boundary::id`. Constraint
+// points are left where they are instead of being killed, their slots never go on the dead list
+fn apply_boundary(particle: Particle, killable: bool) -> Particle {
+    var result = particle;
+    let size = vec2<f32>(WIDTH, HEIGHT);
+    var position = (simulation.simulation_to_texture * vec4<f32>(particle.position, 0.0, 1.0)).xy;
+    if (all(position >= vec2<f32>(0.0) && position < size)) {
+        return result;
+    }
+
+    var velocity = (simulation.simulation_to_texture * vec4<f32>(particle.velocity, 0.0, 0.0)).xy;
+    switch (simulation.boundary) {
+        // wrap
+        case 1u: {
+            position = position - floor(position / size) * size;
+        }
+        // clamp, the velocity into the edge is dropped
+        case 2u: {
+            let clamped = clamp(position, vec2<f32>(0.0), size - 1.0);
+            velocity = select(velocity, vec2<f32>(0.0), clamped != position);
+            position = clamped;
+        }
+        // bounce, mirrors the position back over the edge
+        case 3u: {
+            let low = position < vec2<f32>(0.0);
+            let high = position >= size;
+            let hit = low || high;
+            position = select(position, -position, low);
+            position = select(position, 2.0 * (size - 1.0) - position, high);
+            position = clamp(position, vec2<f32>(0.0), size - 1.0);
+            velocity = select(velocity * (1.0 - simulation.friction), -velocity * simulation.restitution, hit);
+        }
+        // kill
+        default: {
+            if (killable) {
+                result.age = result.lifetime;
+            }
+            return result;
+        }
+    }
+
+    result.position = (simulation.texture_to_simulation * vec4<f32>(position, 0.0, 1.0)).xy;
+    result.velocity = (simulation.texture_to_simulation * vec4<f32>(velocity, 0.0, 0.0)).xy;
+    return result;
+}
+
+fn trail_at(position: vec2<f32>) -> f32 {
+    let last = vec2<i32>(i32(WIDTH) - 1, i32(HEIGHT) - 1);
+    return textureLoad(trail, clamp(vec2<i32>(floor(position)), vec2<i32>(0), last), 0).a;
+}
+
+// Physarum agents turn towards the strongest trail their three sensors pick up
+fn follow_trail(particle: Particle, seed: u32) -> Particle {
+    var result = particle;
+    let position = (simulation.simulation_to_texture * vec4<f32>(particle.position, 0.0, 1.0)).xy;
+    let velocity = (simulation.simulation_to_texture * vec4<f32>(particle.velocity, 0.0, 0.0)).xy;
+    let speed = length(velocity);
+    if (speed == 0.0) {
+        return result;
+    }
+
+    var heading = atan2(velocity.y, velocity.x);
+    let angle = simulation.sensor_angle;
+    let reach = simulation.sensor_distance;
+    let forward = trail_at(position + reach * vec2<f32>(cos(heading), sin(heading)));
+    let left = trail_at(position + reach * vec2<f32>(cos(heading - angle), sin(heading - angle)));
+    let right = trail_at(position + reach * vec2<f32>(cos(heading + angle), sin(heading + angle)));
+
+    let turn = simulation.turn_speed * simulation.delta_time;
+    if (forward >= left && forward >= right) {
+        // already heading for the strongest trail
+    } else if (forward < left && forward < right) {
+        heading += (2.0 * randomFloat(seed) - 1.0) * turn;
+    } else if (left > right) {
+        heading -= turn;
+    } else {
+        heading += turn;
+    }
+
+    let steered = vec2<f32>(cos(heading), sin(heading)) * speed;
+    result.velocity = (simulation.texture_to_simulation * vec4<f32>(steered, 0.0, 0.0)).xy;
+    result.rotation = heading;
+    return result;
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    // every slot is carried over into the next state, dead and pinned ones included
+    var particle = previous_particles[id];
+    if (!is_alive(particle) || particle.pinned != 0u) {
+        particles[id] = particle;
+        return;
+    }
+
+    if (simulation.integrator == VERLET) {
+        // the velocity is implied by the previous position, which keeps the corrections
+        // constraints made to the position
+        particle.velocity = (particle.position - particle.previous_position) / simulation.delta_time;
+    }
+#ifdef PHYSARUM
+    particle = follow_trail(particle, hash(id ^ hash(simulation.seed ^ hash(simulation.frame))));
+#endif
+    let acceleration = particle.acceleration + force_field_acceleration(particle) + interaction_forces[id];
+    let velocity = particle.velocity;
+    particle.velocity += acceleration * simulation.delta_time;
+#ifdef BOIDS
+    // boids keep under their top speed and face where they are heading on the texture
+    let texture_velocity = (simulation.simulation_to_texture * vec4<f32>(particle.velocity, 0.0, 0.0)).xy;
+    let speed = length(texture_velocity);
+    if (speed > simulation.max_speed) {
+        particle.velocity *= simulation.max_speed / speed;
+    }
+    if (speed > 0.0) {
+        particle.rotation = atan2(texture_velocity.y, texture_velocity.x);
+    }
+#endif
+    if (simulation.integrator == EXPLICIT_EULER) {
+        particle.position += velocity * simulation.delta_time;
+    } else {
+        particle.position += particle.velocity * simulation.delta_time;
+    }
+    particle.age += simulation.delta_time;
+    if (simulation.has_obstacles != 0u) {
+        particle = collide_with_obstacles(particle);
+    }
+    particle = apply_boundary(particle, id >= simulation.constraint_points);
+    // consistent with whatever collisions did to the velocity
+    particle.previous_position = particle.position - particle.velocity * simulation.delta_time;
+    particles[id] = particle;
+
+    // a constraint slot on the dead list would be handed to the emitter and never come back
+    if (!is_alive(particle) && id >= simulation.constraint_points) {
+        let slot = atomicAdd(&dead_list.count, 1u);
+        dead_list.indices[slot] = id;
+    }
+}
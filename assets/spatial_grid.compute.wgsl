@@ -0,0 +1,382 @@
+#import logic_particles::constants
+#import logic_particles::particle
+
+@group(0) @binding(0)
+var<storage, read> particles: array<Particle>;
+@group(0) @binding(1)
+var<uniform> simulation: SimulationUniform;
+@group(0) @binding(2)
+var<storage, read_write> cell_counts: array<atomic<u32>>;
+@group(0) @binding(3)
+var<storage, read_write> cell_starts: array<u32>;
+@group(0) @binding(4)
+var<storage, read_write> sorted_indices: array<u32>;
+@group(0) @binding(5)
+var<storage, read_write> interaction_forces: array<vec2<f32>>;
+// Density and pressure of every particle, for fluids
+@group(0) @binding(6)
+var<storage, read_write> densities: array<vec2<f32>>;
+
+let PI: f32 = 3.14159265;
+
+// Threads of the single workgroup scanning the cell counts
+let SCAN_THREADS: u32 = 256u;
+
+var<workgroup> partial_sums: array<u32, 256>;
+
+fn cell_count() -> u32 {
+    return simulation.grid_size.x * simulation.grid_size.y;
+}
+
+fn texture_position(particle: Particle) -> vec2<f32> {
+    return (simulation.simulation_to_texture * vec4<f32>(particle.position, 0.0, 1.0)).xy;
+}
+
+// Particles off the texture end up in the border cells
+fn cell_of(position: vec2<f32>) -> vec2<i32> {
+    let cell = vec2<i32>(floor(position / simulation.cell_size));
+    return clamp(cell, vec2<i32>(0), vec2<i32>(simulation.grid_size) - 1);
+}
+
+fn cell_index(cell: vec2<i32>) -> u32 {
+    return u32(cell.y) * simulation.grid_size.x + u32(cell.x);
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn clear_cells(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= cell_count()) {
+        return;
+    }
+    atomicStore(&cell_counts[id], 0u);
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn count(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        return;
+    }
+    atomicAdd(&cell_counts[cell_index(cell_of(texture_position(particle)))], 1u);
+}
+
+// Exclusive prefix sum of the cell counts, every thread sums a run of cells and the
+// run totals are scanned in shared memory
+@compute @workgroup_size(256, 1, 1)
+fn prefix_sum(@builtin(local_invocation_index) thread: u32) {
+    let cells = cell_count();
+    let run = (cells + SCAN_THREADS - 1u) / SCAN_THREADS;
+    let first = min(thread * run, cells);
+    let last = min(first + run, cells);
+
+    var total = 0u;
+    for (var i = first; i < last; i += 1u) {
+        total += atomicLoad(&cell_counts[i]);
+    }
+    partial_sums[thread] = total;
+    workgroupBarrier();
+
+    // inclusive Hillis-Steele scan over the run totals
+    for (var offset = 1u; offset < SCAN_THREADS; offset *= 2u) {
+        var sum = partial_sums[thread];
+        if (thread >= offset) {
+            sum += partial_sums[thread - offset];
+        }
+        workgroupBarrier();
+        partial_sums[thread] = sum;
+        workgroupBarrier();
+    }
+
+    var start = partial_sums[thread] - total;
+    for (var i = first; i < last; i += 1u) {
+        let count = atomicLoad(&cell_counts[i]);
+        cell_starts[i] = start;
+        // scatter counts up from the start of each cell
+        atomicStore(&cell_counts[i], start);
+        start += count;
+    }
+
+    if (thread == SCAN_THREADS - 1u) {
+        cell_starts[cells] = partial_sums[thread];
+    }
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn scatter(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        return;
+    }
+    let slot = atomicAdd(&cell_counts[cell_index(cell_of(texture_position(particle)))], 1u);
+    sorted_indices[slot] = id;
+}
+
+// Sums up the forces from all neighbors within the interaction radius, they can only be
+// in the surrounding cells since cells are at least as large as the radius
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn interact(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        interaction_forces[id] = vec2<f32>(0.0);
+        return;
+    }
+
+    let position = texture_position(particle);
+    let cell = cell_of(position);
+    let radius = simulation.interaction_radius;
+
+    var force = vec2<f32>(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let neighbor_cell = cell + vec2<i32>(x, y);
+            if (any(neighbor_cell < vec2<i32>(0)) || any(neighbor_cell >= vec2<i32>(simulation.grid_size))) {
+                continue;
+            }
+
+            let index = cell_index(neighbor_cell);
+            for (var i = cell_starts[index]; i < cell_starts[index + 1u]; i += 1u) {
+                let other = sorted_indices[i];
+                if (other == id) {
+                    continue;
+                }
+
+                let offset = position - texture_position(particles[other]);
+                let distance = length(offset);
+                if (distance > 0.0 && distance < radius) {
+                    force += offset / distance * simulation.separation * (1.0 - distance / radius);
+                }
+            }
+        }
+    }
+
+    interaction_forces[id] = (simulation.texture_to_simulation * vec4<f32>(force, 0.0, 0.0)).xy;
+}
+
+fn texture_velocity(particle: Particle) -> vec2<f32> {
+    return (simulation.simulation_to_texture * vec4<f32>(particle.velocity, 0.0, 0.0)).xy;
+}
+
+// Boids steering from the visible neighbors: separation, alignment and cohesion
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn flock(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        interaction_forces[id] = vec2<f32>(0.0);
+        return;
+    }
+
+    let position = texture_position(particle);
+    let velocity = texture_velocity(particle);
+    let cell = cell_of(position);
+
+    var separation = vec2<f32>(0.0);
+    var heading = vec2<f32>(0.0);
+    var center = vec2<f32>(0.0);
+    var visible = 0u;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let neighbor_cell = cell + vec2<i32>(x, y);
+            if (any(neighbor_cell < vec2<i32>(0)) || any(neighbor_cell >= vec2<i32>(simulation.grid_size))) {
+                continue;
+            }
+
+            let index = cell_index(neighbor_cell);
+            for (var i = cell_starts[index]; i < cell_starts[index + 1u]; i += 1u) {
+                let other = sorted_indices[i];
+                if (other == id) {
+                    continue;
+                }
+
+                let neighbor = particles[other];
+                let offset = position - texture_position(neighbor);
+                let distance = length(offset);
+                // the separation radius may reach further than the view radius
+                if (distance > 0.0 && distance < simulation.separation_radius) {
+                    separation += offset / distance * (1.0 - distance / simulation.separation_radius);
+                }
+                if (distance >= simulation.view_radius) {
+                    continue;
+                }
+
+                heading += texture_velocity(neighbor);
+                center += texture_position(neighbor);
+                visible += 1u;
+            }
+        }
+    }
+
+    var force = separation * simulation.boids_separation;
+    if (visible > 0u) {
+        let count = f32(visible);
+        force += (heading / count - velocity) * simulation.boids_alignment;
+        force += (center / count - position) * simulation.boids_cohesion;
+    }
+
+    interaction_forces[id] = (simulation.texture_to_simulation * vec4<f32>(force, 0.0, 0.0)).xy;
+}
+
+// Smoothing kernels of 2d sph, all zero beyond the kernel radius
+fn poly6(distance: f32) -> f32 {
+    let h = simulation.kernel_radius;
+    let d = h * h - distance * distance;
+    return 4.0 / (PI * pow(h, 8.0)) * d * d * d;
+}
+
+fn spiky_gradient(distance: f32) -> f32 {
+    let h = simulation.kernel_radius;
+    return -30.0 / (PI * pow(h, 5.0)) * (h - distance) * (h - distance);
+}
+
+fn viscosity_laplacian(distance: f32) -> f32 {
+    let h = simulation.kernel_radius;
+    return 40.0 / (PI * pow(h, 5.0)) * (h - distance);
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn density(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        return;
+    }
+
+    let position = texture_position(particle);
+    let cell = cell_of(position);
+
+    // a particle counts towards its own density
+    var density = poly6(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let neighbor_cell = cell + vec2<i32>(x, y);
+            if (any(neighbor_cell < vec2<i32>(0)) || any(neighbor_cell >= vec2<i32>(simulation.grid_size))) {
+                continue;
+            }
+
+            let index = cell_index(neighbor_cell);
+            for (var i = cell_starts[index]; i < cell_starts[index + 1u]; i += 1u) {
+                let other = sorted_indices[i];
+                let distance = length(position - texture_position(particles[other]));
+                if (other != id && distance < simulation.kernel_radius) {
+                    density += poly6(distance);
+                }
+            }
+        }
+    }
+
+    // fluids don't pull themselves together, so pressure stays positive
+    let pressure = max(simulation.stiffness * (density - simulation.rest_density), 0.0);
+    densities[id] = vec2<f32>(density, pressure);
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn pressure(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        interaction_forces[id] = vec2<f32>(0.0);
+        return;
+    }
+
+    let position = texture_position(particle);
+    let cell = cell_of(position);
+    let own = densities[id];
+
+    var force = vec2<f32>(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let neighbor_cell = cell + vec2<i32>(x, y);
+            if (any(neighbor_cell < vec2<i32>(0)) || any(neighbor_cell >= vec2<i32>(simulation.grid_size))) {
+                continue;
+            }
+
+            let index = cell_index(neighbor_cell);
+            for (var i = cell_starts[index]; i < cell_starts[index + 1u]; i += 1u) {
+                let other = sorted_indices[i];
+                let offset = position - texture_position(particles[other]);
+                let distance = length(offset);
+                if (other == id || distance <= 0.0 || distance >= simulation.kernel_radius) {
+                    continue;
+                }
+
+                let neighbor = densities[other];
+                let shared_pressure = (own.y + neighbor.y) / (2.0 * neighbor.x);
+                force -= offset / distance * shared_pressure * spiky_gradient(distance);
+            }
+        }
+    }
+
+    interaction_forces[id] = force / own.x;
+}
+
+@compute @workgroup_size(#{WORKGROUP_SIZE}, 1, 1)
+fn viscosity(@builtin(global_invocation_id) invocation_id: vec3<u32>,@builtin(num_workgroups) num_workgroups: vec3<u32>) {
+    let id = id(invocation_id, num_workgroups);
+    if (id >= simulation.count) {
+        return;
+    }
+
+    let particle = particles[id];
+    if (!is_alive(particle)) {
+        return;
+    }
+
+    let position = texture_position(particle);
+    let velocity = texture_velocity(particle);
+    let cell = cell_of(position);
+    let own = densities[id];
+
+    var force = vec2<f32>(0.0);
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let neighbor_cell = cell + vec2<i32>(x, y);
+            if (any(neighbor_cell < vec2<i32>(0)) || any(neighbor_cell >= vec2<i32>(simulation.grid_size))) {
+                continue;
+            }
+
+            let index = cell_index(neighbor_cell);
+            for (var i = cell_starts[index]; i < cell_starts[index + 1u]; i += 1u) {
+                let other = sorted_indices[i];
+                let neighbor = particles[other];
+                let distance = length(position - texture_position(neighbor));
+                if (other == id || distance >= simulation.kernel_radius) {
+                    continue;
+                }
+
+                let relative = texture_velocity(neighbor) - velocity;
+                force += relative / densities[other].x * viscosity_laplacian(distance);
+            }
+        }
+    }
+
+    // the pressure pass left its force in texture space for this one to add to
+    let total = interaction_forces[id] + force * simulation.viscosity / own.x;
+    interaction_forces[id] = (simulation.texture_to_simulation * vec4<f32>(total, 0.0, 0.0)).xy;
+}
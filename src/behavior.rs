@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+
+use crate::particle::SimulationUniform;
+
+/// How a system's particles move besides the forces acting on them.
+#[derive(Reflect, FromReflect, Clone, Debug, Default)]
+pub enum Behavior {
+    /// Particles only follow forces.
+    #[default]
+    Ballistic,
+    Boids(Boids),
+    Fluid(Fluid),
+    Physarum(Physarum),
+    NBody(NBody),
+}
+
+impl Behavior {
+    /// How far particles look for neighbors, `None` when they don't.
+    pub fn neighbor_radius(&self) -> Option<f32> {
+        match self {
+            Behavior::Ballistic => None,
+            Behavior::Boids(boids) => Some(boids.view_radius.max(boids.separation_radius)),
+            Behavior::Fluid(fluid) => Some(fluid.kernel_radius),
+            Behavior::Physarum(_) | Behavior::NBody(_) => None,
+        }
+    }
+
+    /// Fills in the behavior's part of the simulation uniform, after the grid's.
+    pub fn write_uniform(&self, uniform: &mut SimulationUniform) {
+        match self {
+            Behavior::Ballistic => {}
+            Behavior::Boids(boids) => {
+                // neighbors are only searched for in the surrounding cells
+                uniform.view_radius = boids.view_radius.min(uniform.cell_size);
+                uniform.separation_radius = boids.separation_radius.min(uniform.cell_size);
+                uniform.boids_separation = boids.separation;
+                uniform.boids_alignment = boids.alignment;
+                uniform.boids_cohesion = boids.cohesion;
+                uniform.max_speed = boids.max_speed;
+            }
+            Behavior::Fluid(fluid) => {
+                uniform.kernel_radius = fluid.kernel_radius.min(uniform.cell_size);
+                uniform.rest_density = fluid.rest_density;
+                uniform.stiffness = fluid.stiffness;
+                uniform.viscosity = fluid.viscosity;
+            }
+            Behavior::Physarum(physarum) => {
+                uniform.sensor_angle = physarum.sensor_angle;
+                uniform.sensor_distance = physarum.sensor_distance;
+                uniform.turn_speed = physarum.turn_speed;
+                uniform.deposit = physarum.deposit;
+                uniform.decay = physarum.decay;
+            }
+            Behavior::NBody(n_body) => {
+                uniform.gravitational_constant = n_body.gravitational_constant;
+                uniform.softening = n_body.softening;
+            }
+        }
+    }
+}
+
+/// Flocking, every particle steers by the neighbors it can see. Distances are in
+/// texture pixels.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Boids {
+    /// Neighbors further away are ignored.
+    pub view_radius: f32,
+    /// Neighbors closer than this are avoided.
+    pub separation_radius: f32,
+    /// Steering away from neighbors that are too close.
+    pub separation: f32,
+    /// Steering towards the average heading of the neighbors.
+    pub alignment: f32,
+    /// Steering towards the center of the neighbors.
+    pub cohesion: f32,
+    pub max_speed: f32,
+}
+
+impl Default for Boids {
+    fn default() -> Self {
+        Self {
+            view_radius: 12.0,
+            separation_radius: 4.0,
+            separation: 400.0,
+            alignment: 2.0,
+            cohesion: 1.0,
+            max_speed: 60.0,
+        }
+    }
+}
+
+/// Smoothed particle hydrodynamics, particles push apart where they are denser than at rest
+/// and drag their neighbors along. Distances are in texture pixels, every particle weighs 1.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Fluid {
+    /// How far a particle's influence reaches.
+    pub kernel_radius: f32,
+    /// Particles per square pixel the fluid settles at.
+    pub rest_density: f32,
+    /// How hard the fluid pushes back against compression.
+    pub stiffness: f32,
+    pub viscosity: f32,
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Self {
+            kernel_radius: 8.0,
+            rest_density: 0.1,
+            stiffness: 200.0,
+            viscosity: 50.0,
+        }
+    }
+}
+
+/// Slime mold agents, particles follow the trail left on the rendered texture and add to
+/// it. The texture is blurred and faded every simulation step instead of being cleared.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Physarum {
+    /// Radians between the forward sensor and the ones to the left and right.
+    pub sensor_angle: f32,
+    /// How far ahead the sensors are, in texture pixels.
+    pub sensor_distance: f32,
+    /// Radians per second agents turn towards the strongest trail.
+    pub turn_speed: f32,
+    /// How much of the particle color is added to the trail every simulation step.
+    pub deposit: f32,
+    /// Fraction of the trail that fades per second.
+    pub decay: f32,
+}
+
+impl Default for Physarum {
+    fn default() -> Self {
+        Self {
+            sensor_angle: 0.4,
+            sensor_distance: 9.0,
+            turn_speed: 6.0,
+            deposit: 0.1,
+            decay: 0.5,
+        }
+    }
+}
+
+/// Every particle attracts every other one by its `Particle::mass`. All pairs are summed up,
+/// in tiles shared by a workgroup, so this is meant for up to tens of thousands of particles.
+/// Distances are in simulation units.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct NBody {
+    pub gravitational_constant: f32,
+    /// Added to every distance, keeps close encounters from flinging particles away.
+    pub softening: f32,
+}
+
+impl Default for NBody {
+    fn default() -> Self {
+        Self {
+            gravitational_constant: 20.0,
+            softening: 4.0,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use bevy::{prelude::*, render::render_resource::*};
+
+/// How overlapping particles combine. Both render modes work from premultiplied colors, the
+/// texture mode sums particles up order independently, so `Alpha` there is a weighted average.
+///
+/// In `RenderMode::Texture` the modes only apply between particles of the same system, the
+/// sprite showing the texture is alpha blended over the scene like any other. `Multiply` and
+/// `Screen` can't see what is behind the sprite, so they resolve to the combined tint, which
+/// covers the scene instead of darkening or brightening it. Use `RenderMode::Quads` for that.
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    #[default]
+    Alpha,
+    /// Adds up, for fire and glow.
+    Additive,
+    /// Like `Alpha`, for particle colors that are already multiplied by their alpha.
+    Premultiplied,
+    /// Darkens what is behind by the particle color, see above for the texture mode.
+    Multiply,
+    /// Brightens what is behind, without blowing out like `Additive`. See above for the
+    /// texture mode.
+    Screen,
+}
+
+impl BlendMode {
+    pub fn id(&self) -> u32 {
+        match self {
+            BlendMode::Alpha => 0,
+            BlendMode::Additive => 1,
+            BlendMode::Premultiplied => 2,
+            BlendMode::Multiply => 3,
+            BlendMode::Screen => 4,
+        }
+    }
+
+    /// Blending of the quads pipeline, for premultiplied fragment colors.
+    pub fn blend_state(&self) -> BlendState {
+        // the modes that only tint what is behind leave its alpha alone
+        let keep_alpha = BlendComponent {
+            src_factor: BlendFactor::Zero,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let color = |src_factor, dst_factor| BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: BlendOperation::Add,
+        };
+
+        match self {
+            BlendMode::Alpha | BlendMode::Premultiplied => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            BlendMode::Additive => BlendState {
+                color: color(BlendFactor::One, BlendFactor::One),
+                alpha: keep_alpha,
+            },
+            BlendMode::Multiply => BlendState {
+                color: color(BlendFactor::Dst, BlendFactor::OneMinusSrcAlpha),
+                alpha: keep_alpha,
+            },
+            BlendMode::Screen => BlendState {
+                color: color(BlendFactor::One, BlendFactor::OneMinusSrc),
+                alpha: keep_alpha,
+            },
+        }
+    }
+}
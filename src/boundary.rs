@@ -0,0 +1,41 @@
+use bevy::prelude::*;
+
+/// What happens to particles leaving the rendered texture.
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Boundary {
+    /// Constraint points are spared, they just carry on past the edge.
+    #[default]
+    Kill,
+    /// Particles reappear on the opposite edge.
+    Wrap,
+    /// Particles stop at the edge and slide along it.
+    Clamp,
+    Bounce {
+        /// Fraction of the speed into the edge that is kept, 1 bounces back without loss.
+        restitution: f32,
+        /// Fraction of the speed along the edge lost on every bounce.
+        friction: f32,
+    },
+}
+
+impl Boundary {
+    pub fn id(&self) -> u32 {
+        match self {
+            Boundary::Kill => 0,
+            Boundary::Wrap => 1,
+            Boundary::Clamp => 2,
+            Boundary::Bounce { .. } => 3,
+        }
+    }
+
+    /// Restitution and friction, zero for every mode but bounce.
+    pub fn bounce(&self) -> (f32, f32) {
+        match *self {
+            Boundary::Bounce {
+                restitution,
+                friction,
+            } => (restitution, friction),
+            _ => (0.0, 0.0),
+        }
+    }
+}
@@ -1,35 +1,162 @@
 use std::{borrow::Cow, ops::Deref};
 
 use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
     prelude::*,
+    reflect::TypeUuid,
     render::{
         render_resource::*,
         renderer::{RenderContext, RenderDevice, RenderQueue},
     },
+    utils::BoxedFuture,
 };
 use wgpu::Maintain;
 
-use crate::{Particle, HEIGHT, PARTICLE_COUNT, WIDTH, WORKGROUP_SIZE};
+use crate::{HEIGHT, TILE_SIZE, WIDTH, WORKGROUP_SIZE};
+
+pub const CONSTANTS_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5a1d_0c0f_fee0_0002);
+
+/// Rust side constants, available to shaders through `#import logic_particles::constants`.
+pub fn constants_shader() -> Shader {
+    Shader::from_wgsl(format!(
+        "#define_import_path logic_particles::constants
+
+let WIDTH: f32 = {:?};
+let HEIGHT: f32 = {:?};
+let WORKGROUP_SIZE: u32 = {}u;
+let TILE_SIZE: u32 = {}u;
+",
+        WIDTH, HEIGHT, WORKGROUP_SIZE, TILE_SIZE
+    ))
+}
+
+/// naga only accepts literals in `@workgroup_size`, so those can't come from the constants
+/// module and are filled into the `#{WORKGROUP_SIZE}` and `#{TILE_SIZE}` placeholders before
+/// the shader is added.
+fn templated_shader(source: &str) -> Shader {
+    Shader::from_wgsl(
+        source
+            .replace("#{WORKGROUP_SIZE}", &WORKGROUP_SIZE.to_string())
+            .replace("#{TILE_SIZE}", &TILE_SIZE.to_string()),
+    )
+}
+
+/// Loads `.compute.wgsl` assets through `templated_shader`. Shader defs in this bevy version
+/// can't carry values, and going through the asset server keeps shader hot reloading working.
+#[derive(Default)]
+pub struct ComputeShaderLoader;
+
+impl AssetLoader for ComputeShaderLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let shader = templated_shader(std::str::from_utf8(bytes)?);
+            load_context.set_default_asset(LoadedAsset::new(shader));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        // the asset server tries the longest extension first, so plain `.wgsl` is left alone
+        &["compute.wgsl"]
+    }
+}
 
 pub fn compute_pipeline_descriptor(
     shader: Handle<Shader>,
     entry_point: &str,
     bind_group_layout: &BindGroupLayout,
+    shader_defs: Vec<String>,
 ) -> ComputePipelineDescriptor {
     ComputePipelineDescriptor {
         label: None,
         layout: Some(vec![bind_group_layout.clone()]),
         shader,
-        shader_defs: vec![],
+        shader_defs,
         entry_point: Cow::from(entry_point.to_owned()),
     }
 }
 
+/// Layout entry for a uniform buffer read by a compute pass.
+pub fn uniform_entry(binding: u32, min_binding_size: BufferSize) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: Some(min_binding_size),
+        },
+        count: None,
+    }
+}
+
+/// Layout entry for a storage buffer used by a compute pass, of any size.
+pub fn storage_entry(binding: u32, read_only: bool) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// wgpu's default limit for a single dispatch dimension
+const MAX_WORKGROUPS_PER_DIMENSION: u32 = 65535;
+
+/// Dispatches one invocation per particle, rounded up to whole workgroups.
+/// Large counts spill over into y, so shaders must derive their index from `num_workgroups`
+/// and bounds check it against the particle count.
 pub fn run_compute_pass(
     render_context: &mut RenderContext,
     bind_group: &BindGroup,
     pipeline_cache: &PipelineCache,
     pipeline: CachedComputePipelineId,
+    particle_count: u32,
+) {
+    run_compute_pass_sized(
+        render_context,
+        bind_group,
+        pipeline_cache,
+        pipeline,
+        particle_count,
+        WORKGROUP_SIZE,
+    );
+}
+
+/// `run_compute_pass` for passes with `TILE_SIZE` workgroups.
+pub fn run_tiled_compute_pass(
+    render_context: &mut RenderContext,
+    bind_group: &BindGroup,
+    pipeline_cache: &PipelineCache,
+    pipeline: CachedComputePipelineId,
+    particle_count: u32,
+) {
+    run_compute_pass_sized(
+        render_context,
+        bind_group,
+        pipeline_cache,
+        pipeline,
+        particle_count,
+        TILE_SIZE,
+    );
+}
+
+fn run_compute_pass_sized(
+    render_context: &mut RenderContext,
+    bind_group: &BindGroup,
+    pipeline_cache: &PipelineCache,
+    pipeline: CachedComputePipelineId,
+    particle_count: u32,
+    workgroup_size: u32,
 ) {
     let mut pass = render_context
         .command_encoder
@@ -40,7 +167,9 @@ pub fn run_compute_pass(
     let pipeline = pipeline_cache.get_compute_pipeline(pipeline).unwrap();
     pass.set_pipeline(pipeline);
 
-    pass.dispatch_workgroups(PARTICLE_COUNT / WORKGROUP_SIZE, 1, 1);
+    let workgroups = particle_count.div_ceil(workgroup_size);
+    let x = workgroups.clamp(1, MAX_WORKGROUPS_PER_DIMENSION);
+    pass.dispatch_workgroups(x, workgroups.div_ceil(x), 1);
 }
 
 //ugh lazy dupe
@@ -60,8 +189,8 @@ pub fn run_compute_pass_2d(
     pass.set_pipeline(pipeline);
 
     pass.dispatch_workgroups(
-        WIDTH as u32 / WORKGROUP_SIZE,
-        HEIGHT as u32 / WORKGROUP_SIZE,
+        (WIDTH as u32).div_ceil(WORKGROUP_SIZE),
+        (HEIGHT as u32).div_ceil(WORKGROUP_SIZE),
         1,
     );
 }
@@ -71,12 +200,11 @@ pub fn run_compute_pass_2d(
 pub fn read_buffer(buffer: &Buffer, device: &RenderDevice, queue: &RenderQueue) {
     let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
 
-    //FIXME this could come from buffer.size
-    let scratch = [0; (Particle::SHADER_SIZE.get() * PARTICLE_COUNT as u64) as usize];
-    let dest = device.create_buffer_with_data(&BufferInitDescriptor {
+    let dest = device.create_buffer(&BufferDescriptor {
         label: None,
+        size: buffer.size(),
         usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
-        contents: scratch.as_ref(),
+        mapped_at_creation: false,
     });
 
     encoder.copy_buffer_to_buffer(buffer, 0, &dest, 0, buffer.size());
@@ -0,0 +1,443 @@
+use bevy::{
+    prelude::*,
+    render::{render_resource::*, renderer::RenderDevice},
+    utils::HashSet,
+};
+
+use crate::{
+    compute_utils::{compute_pipeline_descriptor, storage_entry, uniform_entry},
+    particle::{
+        shader_bytes, ConstraintData, ConstraintPointData, EmitterUniform, SimulationUniform,
+    },
+    particle_system::ParticleSystemRender,
+};
+
+/// Particles held together by distance constraints, for ropes, chains and soft bodies.
+/// The points take up the first slots of the particle buffer and are placed when the system
+/// starts, the emitter only fills the slots after them. Constraints are laid out on the gpu
+/// once, later changes only affect `iterations`.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Constraints {
+    pub points: Vec<ConstraintPoint>,
+    pub links: Vec<DistanceConstraint>,
+    /// Solver passes over all links after every update step, more keep long chains stiffer.
+    pub iterations: u32,
+}
+
+/// A particle placed at `position`, in texture pixels relative to the emitter like its shapes.
+#[derive(Reflect, FromReflect, Clone, Debug, Default)]
+pub struct ConstraintPoint {
+    pub position: Vec2,
+    /// Pinned points stay where they are placed.
+    pub pinned: bool,
+}
+
+/// Keeps two points `rest_length` apart, `a` and `b` index into `Constraints::points`.
+#[derive(Reflect, FromReflect, Clone, Debug, Default)]
+pub struct DistanceConstraint {
+    pub a: u32,
+    pub b: u32,
+    pub rest_length: f32,
+    /// Fraction of the error corrected every iteration, 1 is rigid.
+    pub stiffness: f32,
+}
+
+impl Default for Constraints {
+    fn default() -> Self {
+        Self::rope(Vec2::new(-60.0, -100.0), Vec2::new(60.0, -100.0), 24)
+    }
+}
+
+impl Constraints {
+    /// A rope of `segments` links from `start` to `end`, pinned at `start`.
+    pub fn rope(start: Vec2, end: Vec2, segments: u32) -> Self {
+        let segments = segments.max(1);
+        let points = (0..=segments)
+            .map(|i| ConstraintPoint {
+                position: start.lerp(end, i as f32 / segments as f32),
+                pinned: i == 0,
+            })
+            .collect::<Vec<_>>();
+        let links = (0..segments)
+            .map(|i| DistanceConstraint::between(&points, i, i + 1, 1.0))
+            .collect();
+
+        Self {
+            points,
+            links,
+            iterations: 8,
+        }
+    }
+
+    /// A cloth of `size` points `spacing` apart, centered on `center` and pinned at its top
+    /// corners. Diagonal links keep it from shearing.
+    pub fn cloth(center: Vec2, size: UVec2, spacing: f32) -> Self {
+        let size = size.max(UVec2::splat(2));
+        let top_left = center - (size - 1).as_vec2() * spacing / 2.0;
+        let index = |x: u32, y: u32| y * size.x + x;
+
+        let mut points = Vec::new();
+        for y in 0..size.y {
+            for x in 0..size.x {
+                points.push(ConstraintPoint {
+                    position: top_left + UVec2::new(x, y).as_vec2() * spacing,
+                    pinned: y == 0 && (x == 0 || x == size.x - 1),
+                });
+            }
+        }
+
+        let mut links = Vec::new();
+        for y in 0..size.y {
+            for x in 0..size.x {
+                if x + 1 < size.x {
+                    links.push(DistanceConstraint::between(
+                        &points,
+                        index(x, y),
+                        index(x + 1, y),
+                        1.0,
+                    ));
+                }
+                if y + 1 < size.y {
+                    links.push(DistanceConstraint::between(
+                        &points,
+                        index(x, y),
+                        index(x, y + 1),
+                        1.0,
+                    ));
+                }
+                if x + 1 < size.x && y + 1 < size.y {
+                    links.push(DistanceConstraint::between(
+                        &points,
+                        index(x, y),
+                        index(x + 1, y + 1),
+                        0.5,
+                    ));
+                    links.push(DistanceConstraint::between(
+                        &points,
+                        index(x + 1, y),
+                        index(x, y + 1),
+                        0.5,
+                    ));
+                }
+            }
+        }
+
+        Self {
+            points,
+            links,
+            iterations: 8,
+        }
+    }
+
+    /// Points beyond the capacity are dropped along with their links.
+    pub fn point_count(&self, capacity: u32) -> u32 {
+        (self.points.len() as u32).min(capacity)
+    }
+
+    /// Splits the links into batches that share no particle, so every batch can be solved in
+    /// a single pass without two threads writing the same particle.
+    fn batches(&self, capacity: u32) -> Vec<Vec<ConstraintData>> {
+        let count = self.point_count(capacity);
+        let mut batches: Vec<(HashSet<u32>, Vec<ConstraintData>)> = Vec::new();
+        for link in &self.links {
+            if link.a == link.b || link.a >= count || link.b >= count {
+                continue;
+            }
+
+            let free = batches
+                .iter()
+                .position(|(used, _)| !used.contains(&link.a) && !used.contains(&link.b));
+            let (used, batch) = match free {
+                Some(index) => &mut batches[index],
+                None => {
+                    batches.push(default());
+                    batches.last_mut().unwrap()
+                }
+            };
+            used.insert(link.a);
+            used.insert(link.b);
+            batch.push(link.data());
+        }
+
+        batches.into_iter().map(|(_, batch)| batch).collect()
+    }
+}
+
+impl DistanceConstraint {
+    /// A constraint keeping `a` and `b` as far apart as they are placed.
+    pub fn between(points: &[ConstraintPoint], a: u32, b: u32, stiffness: f32) -> Self {
+        Self {
+            a,
+            b,
+            rest_length: points[a as usize]
+                .position
+                .distance(points[b as usize].position),
+            stiffness,
+        }
+    }
+
+    fn data(&self) -> ConstraintData {
+        ConstraintData {
+            a: self.a,
+            b: self.b,
+            rest_length: self.rest_length,
+            stiffness: self.stiffness,
+        }
+    }
+}
+
+/// Gpu side of a system's constraints. The batches are stored back to back in `links`,
+/// padded so each one starts at a valid storage buffer offset.
+pub struct ConstraintBuffers {
+    points: Buffer,
+    links: Buffer,
+    batches: Vec<Batch>,
+}
+
+/// Where a batch is in the links buffer, in bytes.
+struct Batch {
+    offset: u64,
+    size: u64,
+    count: u32,
+}
+
+impl ConstraintBuffers {
+    pub fn new(render_device: &RenderDevice, constraints: &Constraints, capacity: u32) -> Self {
+        let count = constraints.point_count(capacity) as usize;
+        let mut points = constraints.points[..count]
+            .iter()
+            .map(|point| ConstraintPointData {
+                position: point.position,
+                pinned: point.pinned as u32,
+            })
+            .collect::<Vec<_>>();
+        // bindings can't be empty, the point count comes from the simulation uniform
+        if points.is_empty() {
+            points.push(default());
+        }
+
+        let alignment = render_device.limits().min_storage_buffer_offset_alignment as u64;
+        let per_alignment = (alignment / ConstraintData::SHADER_SIZE.get()).max(1) as usize;
+        let mut batches = constraints.batches(capacity);
+        // the place pass needs a bind group even without links
+        if batches.is_empty() {
+            batches.push(Vec::new());
+        }
+
+        let link_size = ConstraintData::SHADER_SIZE.get();
+        let mut links = Vec::new();
+        let mut offsets = Vec::new();
+        for batch in batches {
+            let padded = batch.len().max(1).div_ceil(per_alignment) * per_alignment;
+            offsets.push(Batch {
+                offset: links.len() as u64 * link_size,
+                size: padded as u64 * link_size,
+                count: batch.len() as u32,
+            });
+            links.extend_from_slice(&batch);
+            links.resize(
+                links.len() + padded - batch.len(),
+                ConstraintData::default(),
+            );
+        }
+
+        let buffer = |contents: &[u8]| {
+            render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: BufferUsages::STORAGE,
+            })
+        };
+
+        Self {
+            points: buffer(&shader_bytes(&points)),
+            links: buffer(&shader_bytes(&links)),
+            batches: offsets,
+        }
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct ConstraintPipeline {
+    bind_group_layout: BindGroupLayout,
+    pub place_pipeline: CachedComputePipelineId,
+    pub solve_pipeline: CachedComputePipelineId,
+}
+
+/// One bind group per batch with its link count, every one of them binds all points.
+/// Constraints are solved on particle buffer `index` in place.
+pub fn constraint_bind_groups(
+    entity: Entity,
+    render_device: &RenderDevice,
+    constraint_pipeline: &ConstraintPipeline,
+    particle_system_render: &ParticleSystemRender,
+    index: usize,
+) -> Vec<(BindGroup, u32)> {
+    let buffers = &particle_system_render.constraints[&entity];
+
+    buffers
+        .batches
+        .iter()
+        .map(|batch| {
+            let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &constraint_pipeline.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(
+                            particle_system_render.particle_buffers[&entity][index]
+                                .as_entire_buffer_binding(),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: particle_system_render.simulation_uniforms[&entity]
+                            .binding()
+                            .unwrap(),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer: &buffers.links,
+                            offset: batch.offset,
+                            size: BufferSize::new(batch.size),
+                        }),
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::Buffer(
+                            buffers.points.as_entire_buffer_binding(),
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 4,
+                        resource: particle_system_render.emitter_uniforms[&entity]
+                            .binding()
+                            .unwrap(),
+                    },
+                ],
+            });
+            (bind_group, batch.count)
+        })
+        .collect()
+}
+
+impl FromWorld for ConstraintPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        storage_entry(0, false),
+                        uniform_entry(1, SimulationUniform::min_size()),
+                        storage_entry(2, true),
+                        storage_entry(3, true),
+                        uniform_entry(4, EmitterUniform::min_size()),
+                    ],
+                });
+        let shader = world
+            .resource::<AssetServer>()
+            .load("constraint.compute.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let mut queue = |entry_point| {
+            pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+                shader.clone(),
+                entry_point,
+                &bind_group_layout,
+                vec![],
+            ))
+        };
+
+        ConstraintPipeline {
+            place_pipeline: queue("place"),
+            solve_pipeline: queue("solve"),
+            bind_group_layout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(a: u32, b: u32) -> DistanceConstraint {
+        DistanceConstraint {
+            a,
+            b,
+            rest_length: 1.0,
+            stiffness: 1.0,
+        }
+    }
+
+    fn assert_batches_share_no_point(batches: &[Vec<ConstraintData>]) {
+        for batch in batches {
+            let mut used = HashSet::default();
+            for link in batch {
+                assert!(used.insert(link.a), "point {} shared in a batch", link.a);
+                assert!(used.insert(link.b), "point {} shared in a batch", link.b);
+            }
+        }
+    }
+
+    fn link_count(batches: &[Vec<ConstraintData>]) -> usize {
+        batches.iter().map(Vec::len).sum()
+    }
+
+    #[test]
+    fn rope_batches_alternate_links() {
+        let rope = Constraints::rope(Vec2::ZERO, Vec2::new(10.0, 0.0), 10);
+        assert_eq!(rope.points.len(), 11);
+        assert!(rope.points[0].pinned);
+        assert!(rope.points[1..].iter().all(|point| !point.pinned));
+
+        let batches = rope.batches(u32::MAX);
+        assert_batches_share_no_point(&batches);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(link_count(&batches), 10);
+    }
+
+    #[test]
+    fn cloth_batches_share_no_point() {
+        let cloth = Constraints::cloth(Vec2::ZERO, UVec2::new(4, 3), 2.0);
+        assert_eq!(cloth.points.len(), 12);
+        // 3 * 3 horizontal, 4 * 2 vertical and 2 diagonals in each of 3 * 2 cells
+        assert_eq!(cloth.links.len(), 29);
+
+        let batches = cloth.batches(u32::MAX);
+        assert_batches_share_no_point(&batches);
+        assert_eq!(link_count(&batches), cloth.links.len());
+    }
+
+    #[test]
+    fn batches_drop_links_beyond_the_capacity() {
+        let rope = Constraints::rope(Vec2::ZERO, Vec2::new(10.0, 0.0), 10);
+        assert_eq!(rope.point_count(4), 4);
+
+        let batches = rope.batches(4);
+        assert_batches_share_no_point(&batches);
+        assert_eq!(link_count(&batches), 3);
+        assert!(batches
+            .iter()
+            .flatten()
+            .all(|link| link.a < 4 && link.b < 4));
+
+        assert!(rope.batches(0).is_empty());
+    }
+
+    #[test]
+    fn batches_drop_self_links() {
+        let constraints = Constraints {
+            points: vec![default(); 3],
+            links: vec![link(0, 0), link(0, 1), link(1, 2), link(2, 2)],
+            iterations: 1,
+        };
+
+        let batches = constraints.batches(u32::MAX);
+        assert_batches_share_no_point(&batches);
+        assert_eq!(link_count(&batches), 2);
+    }
+}
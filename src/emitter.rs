@@ -0,0 +1,350 @@
+use bevy::{prelude::*, render::extract_component::ExtractComponent};
+use std::f32::consts::PI;
+
+use crate::{
+    particle::EmitterUniform, particle_system::SimulationTime, ParticleSystem, HEIGHT,
+    TEXTURE_SCALE, WIDTH,
+};
+
+/// The space particles are simulated in.
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimulationSpace {
+    /// Particles keep following the entity after they are spawned.
+    #[default]
+    Local,
+    /// Particles stay where they were spawned when the entity moves.
+    World,
+}
+
+/// Matrices between the spaces a system deals with. Canvas space is texture pixels with y
+/// pointing down, in world space mode particles live in world space scaled and flipped the same way.
+pub struct SpaceTransforms {
+    pub emitter_to_simulation: Mat4,
+    pub simulation_to_texture: Mat4,
+    pub world_to_simulation: Mat4,
+}
+
+impl SpaceTransforms {
+    pub fn new(space: SimulationSpace, transform: &GlobalTransform) -> Self {
+        let texture_center = Mat4::from_translation(Vec3::new(WIDTH / 2.0, HEIGHT / 2.0, 0.0));
+        let world_to_canvas =
+            Mat4::from_scale(Vec3::new(1.0 / TEXTURE_SCALE, -1.0 / TEXTURE_SCALE, 1.0));
+        let canvas_to_world = world_to_canvas.inverse();
+        let transform = transform.compute_matrix();
+
+        match space {
+            SimulationSpace::Local => Self {
+                emitter_to_simulation: texture_center,
+                simulation_to_texture: Mat4::IDENTITY,
+                world_to_simulation: texture_center * world_to_canvas * transform.inverse(),
+            },
+            SimulationSpace::World => Self {
+                emitter_to_simulation: world_to_canvas * transform * canvas_to_world,
+                simulation_to_texture: texture_center
+                    * world_to_canvas
+                    * transform.inverse()
+                    * canvas_to_world,
+                world_to_simulation: world_to_canvas,
+            },
+        }
+    }
+}
+
+/// The emitting entity's transform, extracted into the render world.
+#[derive(Component, Clone, Copy)]
+pub struct EmitterTransform(pub GlobalTransform);
+
+impl ExtractComponent for EmitterTransform {
+    type Query = &'static GlobalTransform;
+    type Filter = With<ParticleSystem>;
+
+    fn extract_component(item: bevy::ecs::query::QueryItem<'_, Self::Query>) -> Self {
+        EmitterTransform(*item)
+    }
+}
+
+/// Where new particles appear, relative to the emitter origin.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub enum EmitterShape {
+    Point,
+    Circle {
+        radius: f32,
+    },
+    Ring {
+        inner_radius: f32,
+        outer_radius: f32,
+    },
+    Rectangle {
+        half_extents: Vec2,
+    },
+    Line {
+        start: Vec2,
+        end: Vec2,
+    },
+    /// Spawns on the bright, opaque pixels of an image, one image pixel per canvas pixel.
+    Mask(Handle<Image>),
+}
+
+impl EmitterShape {
+    fn id(&self) -> u32 {
+        match self {
+            EmitterShape::Point => 0,
+            EmitterShape::Circle { .. } => 1,
+            EmitterShape::Ring { .. } => 2,
+            EmitterShape::Rectangle { .. } => 3,
+            EmitterShape::Line { .. } => 4,
+            EmitterShape::Mask(_) => 5,
+        }
+    }
+}
+
+/// Spawns `count` particles at `time` seconds and again every `interval` seconds after that.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Burst {
+    pub time: f32,
+    pub count: u32,
+    /// Seconds between repeats, a burst with no interval fires once.
+    pub interval: f32,
+    /// How often the burst fires, 0 repeats forever.
+    pub cycles: u32,
+}
+
+impl Burst {
+    pub fn once(time: f32, count: u32) -> Self {
+        Self {
+            time,
+            count,
+            interval: 0.0,
+            cycles: 1,
+        }
+    }
+
+    /// Number of times this burst fires in `[start, end)`.
+    fn fires_between(&self, start: f32, end: f32) -> u32 {
+        if self.interval <= 0.0 {
+            return (start <= self.time && self.time < end) as u32;
+        }
+
+        let first = ((start - self.time) / self.interval).ceil().max(0.0) as u32;
+        let mut last = ((end - self.time) / self.interval).ceil().max(0.0) as u32;
+        if self.cycles > 0 {
+            last = last.min(self.cycles);
+        }
+        last.saturating_sub(first)
+    }
+}
+
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Emitter {
+    /// Particles spawned per second.
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    /// Emission pauses while this many particles are alive.
+    pub max_alive: u32,
+    pub shape: EmitterShape,
+    /// Offset of the shape from the entity's transform, in texture pixels.
+    pub origin: Vec2,
+    /// Seconds a particle lives, picked between min and max.
+    pub lifetime: Vec2,
+    /// Initial speed, picked between min and max.
+    pub speed: Vec2,
+    pub direction: Vec2,
+    /// Radians the initial velocity may deviate from `direction`.
+    pub spread: f32,
+    pub acceleration: Vec2,
+    /// Mass of a particle, picked between min and max.
+    pub mass: Vec2,
+    pub color: Color,
+    pub size: f32,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            rate: 200.0,
+            bursts: Vec::new(),
+            max_alive: u32::MAX,
+            shape: EmitterShape::Rectangle {
+                half_extents: Vec2::new(WIDTH, HEIGHT) / 2.0,
+            },
+            origin: Vec2::ZERO,
+            lifetime: Vec2::new(2.0, 6.0),
+            speed: Vec2::new(0.0, 20.0),
+            direction: Vec2::Y,
+            spread: PI,
+            acceleration: Vec2::ZERO,
+            mass: Vec2::ONE,
+            color: Color::RED,
+            size: 1.0,
+        }
+    }
+}
+
+impl Emitter {
+    pub fn uniform(&self, emitter_to_simulation: Mat4) -> EmitterUniform {
+        let (shape_a, shape_b) = match &self.shape {
+            EmitterShape::Point | EmitterShape::Mask(_) => (Vec2::ZERO, Vec2::ZERO),
+            EmitterShape::Circle { radius } => (Vec2::splat(*radius), Vec2::ZERO),
+            EmitterShape::Ring {
+                inner_radius,
+                outer_radius,
+            } => (Vec2::new(*inner_radius, *outer_radius), Vec2::ZERO),
+            EmitterShape::Rectangle { half_extents } => (*half_extents, Vec2::ZERO),
+            EmitterShape::Line { start, end } => (*start, *end),
+        };
+
+        EmitterUniform {
+            transform: emitter_to_simulation,
+            origin: self.origin,
+            shape_a,
+            shape_b,
+            direction: self.direction.normalize_or_zero(),
+            lifetime: self.lifetime,
+            speed: self.speed,
+            acceleration: self.acceleration,
+            mass: self.mass,
+            spread: self.spread,
+            size: self.size,
+            color: Vec4::from(self.color.as_rgba_f32()),
+            shape: self.shape.id(),
+            max_alive: self.max_alive,
+        }
+    }
+
+    pub fn mask(&self) -> Option<&Handle<Image>> {
+        match &self.shape {
+            EmitterShape::Mask(image) => Some(image),
+            _ => None,
+        }
+    }
+}
+
+/// Emission bookkeeping, the emit pass spawns `to_emit` particles each frame.
+#[derive(Component, Clone, Default)]
+pub struct EmitterState {
+    pub elapsed: f32,
+    pub to_emit: u32,
+    accumulator: f32,
+}
+
+impl EmitterState {
+    pub fn tick(&mut self, emitter: &Emitter, seconds: f32) {
+        let start = self.elapsed;
+        let end = start + seconds;
+
+        self.accumulator += emitter.rate * seconds;
+        let continuous = self.accumulator.floor();
+        self.accumulator -= continuous;
+
+        let bursts: u32 = emitter
+            .bursts
+            .iter()
+            .map(|burst| burst.count * burst.fires_between(start, end))
+            .sum();
+
+        self.elapsed = end;
+        self.to_emit = continuous as u32 + bursts;
+    }
+}
+
+impl ExtractComponent for EmitterState {
+    type Query = &'static EmitterState;
+    type Filter = ();
+
+    fn extract_component(item: bevy::ecs::query::QueryItem<'_, Self::Query>) -> Self {
+        item.clone()
+    }
+}
+
+pub fn add_emitter_state(
+    mut commands: Commands,
+    particle_systems: Query<Entity, (With<ParticleSystem>, Without<EmitterState>)>,
+) {
+    for entity in &particle_systems {
+        commands.entity(entity).insert(EmitterState::default());
+    }
+}
+
+// Emission advances with the simulation steps, so it stays in sync with the fixed timestep
+pub fn tick_emitters(
+    time: Res<SimulationTime>,
+    mut particle_systems: Query<(&ParticleSystem, &mut EmitterState)>,
+) {
+    let seconds = time.steps as f32 * time.delta_seconds;
+    for (system, mut state) in &mut particle_systems {
+        state.tick(&system.emitter, seconds);
+        state.to_emit = state.to_emit.min(system.capacity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repeating(interval: f32, cycles: u32) -> Burst {
+        Burst {
+            time: 1.0,
+            count: 5,
+            interval,
+            cycles,
+        }
+    }
+
+    #[test]
+    fn burst_fires_at_its_start_time() {
+        let burst = Burst::once(1.0, 5);
+        assert_eq!(burst.fires_between(1.0, 1.5), 1);
+        assert_eq!(repeating(1.0, 0).fires_between(1.0, 1.5), 1);
+    }
+
+    #[test]
+    fn burst_interval_end_is_exclusive() {
+        let burst = Burst::once(1.0, 5);
+        assert_eq!(burst.fires_between(0.5, 1.0), 0);
+        assert_eq!(burst.fires_between(1.5, 2.0), 0);
+
+        let burst = repeating(1.0, 0);
+        assert_eq!(burst.fires_between(1.5, 2.0), 0);
+        assert_eq!(burst.fires_between(2.0, 4.0), 2);
+        assert_eq!(burst.fires_between(0.0, 10.0), 9);
+    }
+
+    #[test]
+    fn burst_stops_after_its_cycles() {
+        let burst = repeating(1.0, 3);
+        assert_eq!(burst.fires_between(0.0, 10.0), 3);
+        assert_eq!(burst.fires_between(3.0, 10.0), 1);
+        assert_eq!(burst.fires_between(3.5, 10.0), 0);
+    }
+
+    #[test]
+    fn tick_carries_fractional_rate_over() {
+        let emitter = Emitter {
+            rate: 0.25,
+            ..default()
+        };
+        let mut state = EmitterState::default();
+        let emitted: Vec<_> = (0..8)
+            .map(|_| {
+                state.tick(&emitter, 1.0);
+                state.to_emit
+            })
+            .collect();
+        assert_eq!(emitted, [0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn tick_adds_bursts_to_the_rate() {
+        let emitter = Emitter {
+            rate: 2.5,
+            bursts: vec![Burst::once(1.0, 10)],
+            ..default()
+        };
+        let mut state = EmitterState::default();
+        state.tick(&emitter, 1.0);
+        assert_eq!(state.to_emit, 2);
+        state.tick(&emitter, 1.0);
+        assert_eq!(state.to_emit, 13);
+        assert_eq!(state.elapsed, 2.0);
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::{prelude::*, render::Extract, sprite::TextureAtlas, utils::HashMap};
+
+use crate::{particle::SimulationUniform, ParticleSystem};
+
+/// Textures `RenderMode::Quads` particles with a frame of a sprite sheet, tinted by the
+/// particle color, and flips through the frames as the particles age.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Flipbook {
+    pub sheet: SpriteSheet,
+    /// Frames per second of particle age, 0 keeps every particle on its start frame.
+    pub frame_rate: f32,
+    pub playback: Playback,
+    /// Starts every particle on a random frame instead of the first.
+    pub random_start_frame: bool,
+}
+
+impl Default for Flipbook {
+    fn default() -> Self {
+        Self {
+            sheet: SpriteSheet::default(),
+            frame_rate: 12.0,
+            playback: Playback::Loop,
+            random_start_frame: false,
+        }
+    }
+}
+
+impl Flipbook {
+    /// Fills in the flipbook's part of the simulation uniform.
+    pub fn write_uniform(&self, uniform: &mut SimulationUniform) {
+        uniform.frame_rate = self.frame_rate.max(0.0);
+        uniform.playback = self.playback.id();
+        uniform.random_start_frame = self.random_start_frame as u32;
+    }
+}
+
+/// Where the frames of a `Flipbook` come from. Frames are stretched over the particle's
+/// square, so they should be square too.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub enum SpriteSheet {
+    /// An image cut into a grid of equally sized frames, read row by row from the top left.
+    Grid {
+        image: Handle<Image>,
+        columns: u32,
+        rows: u32,
+    },
+    /// The textures of an atlas in the order they were added, for sheets packed unevenly.
+    Atlas(Handle<TextureAtlas>),
+}
+
+impl Default for SpriteSheet {
+    fn default() -> Self {
+        SpriteSheet::Grid {
+            image: Handle::default(),
+            columns: 1,
+            rows: 1,
+        }
+    }
+}
+
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Playback {
+    /// Wraps around to the first frame after the last.
+    #[default]
+    Loop,
+    /// Holds the last frame once it is reached.
+    Once,
+}
+
+impl Playback {
+    pub fn id(&self) -> u32 {
+        match self {
+            Playback::Loop => 0,
+            Playback::Once => 1,
+        }
+    }
+}
+
+/// The image of a system's sheet and the uv rect of every frame in it, min then max.
+#[derive(Clone, PartialEq)]
+pub struct ExtractedFlipbook {
+    pub image: Handle<Image>,
+    pub frames: Vec<Vec4>,
+}
+
+/// The uv rects of a `SpriteSheet::Grid`, row by row from the top left. An empty grid is
+/// read as a single frame.
+pub fn grid_frames(columns: u32, rows: u32) -> Vec<Vec4> {
+    let grid = UVec2::new(columns, rows).max(UVec2::ONE);
+    let cell = Vec2::ONE / grid.as_vec2();
+    (0..grid.x * grid.y)
+        .map(|frame| {
+            let min = UVec2::new(frame % grid.x, frame / grid.x).as_vec2() * cell;
+            let max = min + cell;
+            Vec4::new(min.x, min.y, max.x, max.y)
+        })
+        .collect()
+}
+
+/// Flipbooks of all systems whose sheet has loaded, rebuilt every frame.
+#[derive(Resource, Default)]
+pub struct ExtractedFlipbooks(pub HashMap<Entity, ExtractedFlipbook>);
+
+pub fn extract_flipbooks(
+    mut commands: Commands,
+    particle_systems: Extract<Query<(Entity, &ParticleSystem)>>,
+    atlases: Extract<Res<Assets<TextureAtlas>>>,
+) {
+    let mut extracted = HashMap::default();
+    for (entity, system) in particle_systems.iter() {
+        let Some(flipbook) = &system.flipbook else {
+            continue;
+        };
+        let flipbook = match &flipbook.sheet {
+            SpriteSheet::Grid {
+                image,
+                columns,
+                rows,
+            } => ExtractedFlipbook {
+                image: image.clone_weak(),
+                frames: grid_frames(*columns, *rows),
+            },
+            SpriteSheet::Atlas(atlas) => {
+                let Some(atlas) = atlases.get(atlas) else {
+                    continue;
+                };
+                let frames = atlas
+                    .textures
+                    .iter()
+                    .map(|rect| {
+                        let min = rect.min / atlas.size;
+                        let max = rect.max / atlas.size;
+                        Vec4::new(min.x, min.y, max.x, max.y)
+                    })
+                    .collect();
+                ExtractedFlipbook {
+                    image: atlas.texture.clone_weak(),
+                    frames,
+                }
+            }
+        };
+        if !flipbook.frames.is_empty() {
+            extracted.insert(entity, flipbook);
+        }
+    }
+
+    commands.insert_resource(ExtractedFlipbooks(extracted));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_frames_go_row_by_row() {
+        let frames = grid_frames(4, 2);
+        assert_eq!(frames.len(), 8);
+        assert_eq!(frames[0], Vec4::new(0.0, 0.0, 0.25, 0.5));
+        assert_eq!(frames[1], Vec4::new(0.25, 0.0, 0.5, 0.5));
+        assert_eq!(frames[3], Vec4::new(0.75, 0.0, 1.0, 0.5));
+        assert_eq!(frames[4], Vec4::new(0.0, 0.5, 0.25, 1.0));
+        assert_eq!(frames[7], Vec4::new(0.75, 0.5, 1.0, 1.0));
+    }
+
+    #[test]
+    fn grid_frames_cover_the_sheet() {
+        let frames = grid_frames(3, 5);
+        assert_eq!(frames.len(), 15);
+        let area: f32 = frames
+            .iter()
+            .map(|frame| (frame.z - frame.x) * (frame.w - frame.y))
+            .sum();
+        assert!((area - 1.0).abs() < 1e-5);
+        assert!(frames
+            .iter()
+            .all(|frame| frame.min_element() >= 0.0 && frame.max_element() <= 1.0));
+    }
+
+    #[test]
+    fn empty_grid_is_one_frame() {
+        let whole = vec![Vec4::new(0.0, 0.0, 1.0, 1.0)];
+        assert_eq!(grid_frames(0, 0), whole);
+        assert_eq!(grid_frames(1, 1), whole);
+        assert_eq!(grid_frames(0, 2).len(), 2);
+    }
+}
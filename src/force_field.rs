@@ -0,0 +1,184 @@
+use bevy::{prelude::*, render::Extract};
+
+use crate::particle::{ForceFieldData, ForceFields, MAX_FORCE_FIELDS};
+
+/// Shapes the motion of every particle system, placed with the entity's transform.
+/// Fields in `ParticleSystem::force_fields` only move that system and sit at its transform.
+/// Values are in world units and seconds.
+#[derive(Component, Reflect, FromReflect, Clone, Debug)]
+#[reflect(Component)]
+pub enum ForceField {
+    /// Constant acceleration everywhere.
+    Gravity { acceleration: Vec2 },
+    /// Slows particles down, `linear` scales with speed and `quadratic` with speed squared.
+    Drag { linear: f32, quadratic: f32 },
+    /// Pulls particle velocities towards `velocity`, `strength` is how fast per second.
+    Wind { velocity: Vec2, strength: f32 },
+    /// Accelerates particles within `radius` towards the entity, fading out with
+    /// distance by `falloff`, 0 pulls equally hard across the whole radius.
+    Attractor {
+        strength: f32,
+        radius: f32,
+        falloff: f32,
+    },
+    /// An attractor pushing particles away.
+    Repulsor {
+        strength: f32,
+        radius: f32,
+        falloff: f32,
+    },
+    /// Swirling, divergence free motion from curl noise.
+    Turbulence {
+        /// Acceleration the noise applies, roughly the strongest it gets.
+        amplitude: f32,
+        /// Noise features per world unit.
+        frequency: f32,
+        /// Layers of finer detail, each at double the frequency and half the amplitude.
+        octaves: u32,
+        /// Velocity the noise pattern drifts with.
+        scroll: Vec2,
+        /// How fast the pattern changes over time, 0 keeps it still.
+        evolution: f32,
+    },
+    /// Swirls particles within `radius` around the entity, counterclockwise for a positive
+    /// `strength`, fading out with distance by `falloff` like an attractor.
+    Vortex {
+        strength: f32,
+        radius: f32,
+        falloff: f32,
+        /// Acceleration towards the center on top of the swirl.
+        pull: f32,
+    },
+    /// Steers particles onto a circular orbit around the entity.
+    Orbit {
+        radius: f32,
+        /// Speed along the orbit, counterclockwise when positive.
+        speed: f32,
+        /// How fast per second particles are corrected onto the orbit.
+        strength: f32,
+    },
+}
+
+impl Default for ForceField {
+    fn default() -> Self {
+        ForceField::Gravity {
+            acceleration: Vec2::new(0.0, -90.0),
+        }
+    }
+}
+
+impl ForceField {
+    fn kind(&self) -> u32 {
+        match self {
+            ForceField::Gravity { .. } => 0,
+            ForceField::Drag { .. } => 1,
+            ForceField::Wind { .. } => 2,
+            ForceField::Attractor { .. } | ForceField::Repulsor { .. } => 3,
+            ForceField::Turbulence { .. } => 4,
+            ForceField::Vortex { .. } => 5,
+            ForceField::Orbit { .. } => 6,
+        }
+    }
+
+    pub fn data(&self, transform: &GlobalTransform) -> ForceFieldData {
+        let mut data = ForceFieldData {
+            position: transform.translation().truncate(),
+            kind: self.kind(),
+            ..default()
+        };
+
+        match *self {
+            ForceField::Gravity { acceleration } => data.force = acceleration,
+            ForceField::Drag { linear, quadratic } => data.force = Vec2::new(linear, quadratic),
+            ForceField::Wind { velocity, strength } => {
+                data.force = velocity;
+                data.strength = strength;
+            }
+            ForceField::Attractor {
+                strength,
+                radius,
+                falloff,
+            } => {
+                data.strength = strength;
+                data.radius = radius;
+                data.falloff = falloff;
+            }
+            ForceField::Repulsor {
+                strength,
+                radius,
+                falloff,
+            } => {
+                data.strength = -strength;
+                data.radius = radius;
+                data.falloff = falloff;
+            }
+            ForceField::Turbulence {
+                amplitude,
+                frequency,
+                octaves,
+                scroll,
+                evolution,
+            } => {
+                data.strength = amplitude;
+                data.frequency = frequency;
+                data.octaves = octaves;
+                data.force = scroll;
+                data.evolution = evolution;
+            }
+            ForceField::Vortex {
+                strength,
+                radius,
+                falloff,
+                pull,
+            } => {
+                data.strength = strength;
+                data.radius = radius;
+                data.falloff = falloff;
+                data.force = Vec2::new(pull, 0.0);
+            }
+            ForceField::Orbit {
+                radius,
+                speed,
+                strength,
+            } => {
+                data.strength = strength;
+                data.radius = radius;
+                data.force = Vec2::new(speed, 0.0);
+            }
+        }
+        data
+    }
+}
+
+/// All force fields of the main world, rebuilt every frame. Fields beyond
+/// `MAX_FORCE_FIELDS` are ignored.
+#[derive(Resource, Default)]
+pub struct ExtractedForceFields(pub ForceFields);
+
+impl ExtractedForceFields {
+    /// The global fields followed by the ones of a single system.
+    pub fn with_local(&self, fields: &[ForceField], transform: &GlobalTransform) -> ForceFields {
+        let mut combined = self.0;
+        for field in fields {
+            if combined.count as usize >= MAX_FORCE_FIELDS {
+                break;
+            }
+            combined.fields[combined.count as usize] = field.data(transform);
+            combined.count += 1;
+        }
+        combined
+    }
+}
+
+pub fn extract_force_fields(
+    mut commands: Commands,
+    force_fields: Extract<Query<(&ForceField, &GlobalTransform)>>,
+) {
+    let mut extracted = ForceFields::default();
+    for (field, transform) in force_fields.iter().take(MAX_FORCE_FIELDS) {
+        extracted.fields[extracted.count as usize] = field.data(transform);
+        extracted.count += 1;
+    }
+
+    commands.insert_resource(ExtractedForceFields(extracted));
+}
@@ -0,0 +1,24 @@
+use bevy::prelude::*;
+
+/// How the update pass steps particles forward.
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// Moves with the velocity from before the step, cheapest but gains energy.
+    ExplicitEuler,
+    /// Moves with the velocity after the step, stable for most effects.
+    #[default]
+    SemiImplicitEuler,
+    /// Derives the velocity from the previous position, so position corrections of
+    /// constraints and collisions carry over into the motion.
+    Verlet,
+}
+
+impl Integrator {
+    pub fn id(&self) -> u32 {
+        match self {
+            Integrator::ExplicitEuler => 0,
+            Integrator::SemiImplicitEuler => 1,
+            Integrator::Verlet => 2,
+        }
+    }
+}
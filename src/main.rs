@@ -9,26 +9,109 @@ use bevy_inspector_egui::WorldInspectorPlugin;
 
 pub const HEIGHT: f32 = 480.0;
 pub const WIDTH: f32 = 640.0;
+/// World units per texture pixel of the sprite a system renders into.
+pub const TEXTURE_SCALE: f32 = 3.0;
 
 pub const PARTICLE_COUNT: u32 = 1000;
-// XXX when changing this also change it in the shader... TODO figure out how to avoid that...
 pub const WORKGROUP_SIZE: u32 = 16;
+/// Workgroup size of passes that share a tile of particles in workgroup memory.
+pub const TILE_SIZE: u32 = 128;
 
-#[derive(ShaderType, Default, Clone, Copy)]
-struct Particle {
-    position: Vec2,
-}
-
+mod behavior;
+mod blend;
+mod boundary;
 mod compute_utils;
+mod constraint;
+mod emitter;
+mod flipbook;
+mod force_field;
+mod integrator;
+mod nbody;
+mod obstacle;
+mod particle;
+mod particle_quads;
 mod particle_render;
 mod particle_system;
 mod particle_update;
+mod spatial_grid;
 
+use behavior::Behavior;
+use blend::BlendMode;
+use boundary::Boundary;
+use constraint::Constraints;
+use emitter::{Burst, Emitter, EmitterShape, SimulationSpace};
+use flipbook::Flipbook;
+use force_field::ForceField;
+use integrator::Integrator;
+use obstacle::Obstacles;
+use particle_quads::RenderMode;
 use particle_system::ParticlePlugin;
+use spatial_grid::Interaction;
 
-#[derive(Component, Default, Clone)]
+#[derive(Component, Reflect, Clone)]
+#[reflect(Component)]
 pub struct ParticleSystem {
+    /// What `RenderMode::Texture` and physarum systems plot into, the others can leave it at
+    /// the default. Either way the simulation is bounded by the fixed `WIDTH` by `HEIGHT`
+    /// canvas, which the boundary, neighbor grid and obstacle sdf cover.
     pub rendered_texture: Handle<Image>,
+    pub render_mode: RenderMode,
+    pub blend_mode: BlendMode,
+    /// Textures `RenderMode::Quads` particles, see `Flipbook`.
+    pub flipbook: Option<Flipbook>,
+    /// Number of particle slots allocated on the gpu for this system, 0 leaves it out. Clamped
+    /// to what fits in one storage buffer binding, about 1.7 million with default limits.
+    pub capacity: u32,
+    /// Seed for the random spawn pattern.
+    pub seed: u32,
+    pub emitter: Emitter,
+    pub simulation_space: SimulationSpace,
+    pub boundary: Boundary,
+    pub obstacles: Obstacles,
+    /// Lets particles push each other apart, see `Interaction`.
+    pub interaction: Option<Interaction>,
+    pub behavior: Behavior,
+    pub integrator: Integrator,
+    /// Holds particles together, see `Constraints`.
+    pub constraints: Option<Constraints>,
+    /// Fields that only move this system's particles.
+    pub force_fields: Vec<ForceField>,
+}
+
+impl ParticleSystem {
+    /// Larger of the interaction and behavior radii, what the neighbor grid is laid out for.
+    pub fn neighbor_radius(&self) -> Option<f32> {
+        let interaction = self
+            .interaction
+            .as_ref()
+            .map(|interaction| interaction.radius);
+        match (interaction, self.behavior.neighbor_radius()) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self {
+            rendered_texture: Handle::default(),
+            render_mode: RenderMode::Quads,
+            blend_mode: BlendMode::Alpha,
+            flipbook: None,
+            capacity: PARTICLE_COUNT,
+            seed: 0,
+            emitter: Emitter::default(),
+            simulation_space: SimulationSpace::Local,
+            boundary: Boundary::Kill,
+            obstacles: Obstacles::default(),
+            interaction: None,
+            behavior: Behavior::Ballistic,
+            integrator: Integrator::SemiImplicitEuler,
+            constraints: None,
+            force_fields: Vec::new(),
+        }
+    }
 }
 
 fn main() {
@@ -71,20 +154,42 @@ fn spawn_on_space_bar(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     keyboard: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
 ) {
     if keyboard.pressed(KeyCode::Space) {
+        // the camera sits at the origin, so the cursor is offset by half the window
+        let cursor = windows
+            .get_primary()
+            .and_then(|window| window.cursor_position())
+            .map_or(Vec2::ZERO, |cursor| cursor - Vec2::new(WIDTH, HEIGHT) / 2.0);
+
         let image = create_texture(&mut images);
         commands
             .spawn(SpriteBundle {
                 sprite: Sprite {
-                    custom_size: Some(Vec2::new(WIDTH * 3.0, HEIGHT * 3.0)),
+                    custom_size: Some(Vec2::new(WIDTH, HEIGHT) * TEXTURE_SCALE),
                     ..default()
                 },
                 texture: image.clone(),
+                transform: Transform::from_translation(cursor.extend(0.0)),
                 ..default()
             })
             .insert(ParticleSystem {
                 rendered_texture: image,
+                capacity: 200,
+                emitter: Emitter {
+                    rate: 0.0,
+                    bursts: vec![Burst::once(0.0, 200)],
+                    shape: EmitterShape::Circle { radius: 4.0 },
+                    lifetime: Vec2::new(0.5, 1.5),
+                    speed: Vec2::new(60.0, 180.0),
+                    direction: Vec2::NEG_Y,
+                    spread: std::f32::consts::PI,
+                    color: Color::YELLOW,
+                    ..default()
+                },
+                simulation_space: SimulationSpace::World,
+                ..default()
             });
     }
 }
@@ -94,7 +199,7 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
     commands
         .spawn(SpriteBundle {
             sprite: Sprite {
-                custom_size: Some(Vec2::new(WIDTH * 3.0, HEIGHT * 3.0)),
+                custom_size: Some(Vec2::new(WIDTH, HEIGHT) * TEXTURE_SCALE),
                 ..default()
             },
             texture: image.clone(),
@@ -102,7 +207,15 @@ fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
         })
         .insert(ParticleSystem {
             rendered_texture: image,
+            ..default()
         });
 
+    commands.spawn((
+        ForceField::Gravity {
+            acceleration: Vec2::new(0.0, -90.0),
+        },
+        TransformBundle::default(),
+    ));
+
     commands.spawn(Camera2dBundle::default());
 }
@@ -0,0 +1,111 @@
+use bevy::{
+    prelude::*,
+    render::{render_resource::*, renderer::RenderDevice},
+};
+
+use crate::{
+    compute_utils::compute_pipeline_descriptor, particle::SimulationUniform,
+    particle_system::ParticleSystemRender,
+};
+
+/// Sums up the gravity between all particles of `NBody` systems into their interaction forces.
+#[derive(Resource, Clone)]
+pub struct NBodyPipeline {
+    bind_group_layout: BindGroupLayout,
+    pub gravity_pipeline: CachedComputePipelineId,
+}
+
+fn bind_group_layout() -> BindGroupLayoutDescriptor<'static> {
+    BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(SimulationUniform::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    }
+}
+
+/// Reads the bodies from particle buffer `index`.
+pub fn nbody_bind_group(
+    entity: Entity,
+    render_device: &RenderDevice,
+    nbody_pipeline: &NBodyPipeline,
+    particle_system_render: &ParticleSystemRender,
+    index: usize,
+) -> BindGroup {
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &nbody_pipeline.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(
+                    particle_system_render.particle_buffers[&entity][index]
+                        .as_entire_buffer_binding(),
+                ),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: particle_system_render.simulation_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Buffer(
+                    particle_system_render.interaction_buffers[&entity].as_entire_buffer_binding(),
+                ),
+            },
+        ],
+    })
+}
+
+impl FromWorld for NBodyPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&bind_group_layout());
+        let shader = world.resource::<AssetServer>().load("nbody.compute.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let gravity_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader,
+            "gravity",
+            &bind_group_layout,
+            vec![],
+        ));
+
+        NBodyPipeline {
+            bind_group_layout,
+            gravity_pipeline,
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::{
+    prelude::*,
+    render::{render_resource::*, renderer::RenderDevice, texture::GpuImage},
+};
+
+use crate::{
+    compute_utils::compute_pipeline_descriptor,
+    particle::{ObstacleShapeData, ObstacleShapes, MAX_OBSTACLE_SHAPES},
+    particle_system::ParticleSystemRender,
+    HEIGHT, WIDTH,
+};
+
+/// Solid areas a system's particles collide with, baked into a signed distance field
+/// over the rendered texture every frame.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Obstacles {
+    /// Opaque pixels are solid, one image pixel per canvas pixel centered on the texture.
+    pub mask: Option<Handle<Image>>,
+    pub shapes: Vec<ObstacleShape>,
+    /// Fraction of the speed into an obstacle that is kept on impact.
+    pub restitution: f32,
+    /// Fraction of the speed along an obstacle lost on impact.
+    pub friction: f32,
+}
+
+impl Default for Obstacles {
+    fn default() -> Self {
+        Self {
+            mask: None,
+            shapes: Vec::new(),
+            restitution: 0.5,
+            friction: 0.1,
+        }
+    }
+}
+
+/// Analytic obstacles in texture pixels from the center of the texture.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub enum ObstacleShape {
+    Circle { center: Vec2, radius: f32 },
+    Box { center: Vec2, half_extents: Vec2 },
+    Capsule { start: Vec2, end: Vec2, radius: f32 },
+}
+
+impl ObstacleShape {
+    fn data(&self) -> ObstacleShapeData {
+        match *self {
+            ObstacleShape::Circle { center, radius } => ObstacleShapeData {
+                a: center,
+                radius,
+                kind: 0,
+                ..default()
+            },
+            ObstacleShape::Box {
+                center,
+                half_extents,
+            } => ObstacleShapeData {
+                a: center,
+                b: half_extents,
+                kind: 1,
+                ..default()
+            },
+            ObstacleShape::Capsule { start, end, radius } => ObstacleShapeData {
+                a: start,
+                b: end,
+                radius,
+                kind: 2,
+            },
+        }
+    }
+}
+
+impl Obstacles {
+    pub fn is_empty(&self) -> bool {
+        self.mask.is_none() && self.shapes.is_empty()
+    }
+
+    /// Shapes beyond `MAX_OBSTACLE_SHAPES` are ignored.
+    pub fn shapes(&self) -> ObstacleShapes {
+        let mut shapes = ObstacleShapes {
+            use_mask: self.mask.is_some() as u32,
+            ..default()
+        };
+        for shape in self.shapes.iter().take(MAX_OBSTACLE_SHAPES) {
+            shapes.shapes[shapes.count as usize] = shape.data();
+            shapes.count += 1;
+        }
+        shapes
+    }
+}
+
+/// The texture the obstacle sdf is baked into, in pixels with negative values inside.
+pub fn create_sdf_texture(render_device: &RenderDevice) -> Texture {
+    render_device.create_texture(&TextureDescriptor {
+        label: None,
+        size: Extent3d {
+            width: WIDTH as u32,
+            height: HEIGHT as u32,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::R32Float,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+    })
+}
+
+#[derive(Resource, Clone)]
+pub struct ObstaclePipeline {
+    bind_group_layout: BindGroupLayout,
+    pub bake_pipeline: CachedComputePipelineId,
+}
+
+fn bind_group_layout() -> BindGroupLayoutDescriptor<'static> {
+    BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::R32Float,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(ObstacleShapes::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ],
+    }
+}
+
+pub fn obstacle_bind_group(
+    entity: Entity,
+    render_device: &RenderDevice,
+    obstacle_pipeline: &ObstaclePipeline,
+    particle_system_render: &ParticleSystemRender,
+    mask: &GpuImage,
+) -> BindGroup {
+    let sdf =
+        particle_system_render.sdf_textures[&entity].create_view(&TextureViewDescriptor::default());
+
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &obstacle_pipeline.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&sdf),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: particle_system_render.obstacle_shapes[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(&mask.texture_view),
+            },
+        ],
+    })
+}
+
+impl FromWorld for ObstaclePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let bind_group_layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(&bind_group_layout());
+        let shader = world
+            .resource::<AssetServer>()
+            .load("obstacle_sdf.compute.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let bake_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader,
+            "bake",
+            &bind_group_layout,
+            vec![],
+        ));
+
+        ObstaclePipeline {
+            bind_group_layout,
+            bake_pipeline,
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{encase, ShaderType},
+};
+
+use crate::{TILE_SIZE, WORKGROUP_SIZE};
+
+pub const PARTICLE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5a1d_0c0f_fee0_0001);
+
+/// Maps a rust field type to the matching wgsl type.
+pub trait WgslType: Sized {
+    const WGSL: &'static str;
+
+    /// A value with no zero bytes, used to find where a field lands in the gpu layout.
+    fn sentinel() -> Self;
+}
+
+macro_rules! impl_wgsl_type {
+    ($($ty:ty => $wgsl:literal, $sentinel:expr;)*) => {
+        $(impl WgslType for $ty {
+            const WGSL: &'static str = $wgsl;
+
+            fn sentinel() -> Self {
+                $sentinel
+            }
+        })*
+    };
+}
+
+const SENTINEL_BITS: u32 = 0x3f80_0101;
+
+impl_wgsl_type!(
+    f32 => "f32", f32::from_bits(SENTINEL_BITS);
+    u32 => "u32", SENTINEL_BITS;
+    i32 => "i32", SENTINEL_BITS as i32;
+    Vec2 => "vec2<f32>", Vec2::splat(f32::from_bits(SENTINEL_BITS));
+    Vec3 => "vec3<f32>", Vec3::splat(f32::from_bits(SENTINEL_BITS));
+    Vec4 => "vec4<f32>", Vec4::splat(f32::from_bits(SENTINEL_BITS));
+    UVec2 => "vec2<u32>", UVec2::splat(SENTINEL_BITS);
+    IVec2 => "vec2<i32>", IVec2::splat(SENTINEL_BITS as i32);
+    Mat4 => "mat4x4<f32>", Mat4::from_cols_array(&[f32::from_bits(SENTINEL_BITS); 16]);
+);
+
+/// Declares a `ShaderType` struct along with the wgsl declaration of the same struct,
+/// so the layout only has to be written down once.
+macro_rules! shader_struct {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $($(#[$field_attr:meta])* pub $field:ident: $ty:ty,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(ShaderType, Default, Clone, Copy, Debug)]
+        pub struct $name {
+            $($(#[$field_attr])* pub $field: $ty,)*
+        }
+
+        impl $name {
+            pub fn wgsl_struct() -> String {
+                $crate::particle::wgsl_struct(
+                    stringify!($name),
+                    &[$((stringify!($field), <$ty as $crate::particle::WgslType>::WGSL)),*],
+                )
+            }
+
+            /// Byte offset of every field as encase writes it.
+            #[cfg(test)]
+            pub fn field_offsets() -> Vec<(&'static str, usize)> {
+                let default = $crate::particle::shader_bytes(&Self::default());
+                vec![$({
+                    let mut value = Self::default();
+                    value.$field = <$ty as $crate::particle::WgslType>::sentinel();
+                    let bytes = $crate::particle::shader_bytes(&value);
+                    let offset = bytes.iter().zip(&default).position(|(a, b)| a != b).unwrap();
+                    (stringify!($field), offset)
+                }),*]
+            }
+        }
+    };
+}
+
+pub fn wgsl_struct(name: &str, fields: &[(&str, &str)]) -> String {
+    let mut wgsl = format!("struct {} {{\n", name);
+    for (field, ty) in fields {
+        wgsl.push_str(&format!("    {}: {},\n", field, ty));
+    }
+    wgsl.push_str("}\n");
+    wgsl
+}
+
+/// The bytes of a value as the gpu reads them from a storage buffer.
+pub fn shader_bytes<T: ShaderType + encase::internal::WriteInto>(value: &T) -> Vec<u8> {
+    let mut buffer = encase::StorageBuffer::new(Vec::new());
+    buffer.write(value).unwrap();
+    buffer.into_inner()
+}
+
+shader_struct! {
+    pub struct Particle {
+        pub position: Vec2,
+        pub velocity: Vec2,
+        pub acceleration: Vec2,
+        pub size: f32,
+        pub rotation: f32,
+        pub color: Vec4,
+        pub age: f32,
+        pub lifetime: f32,
+        /// Only n-body gravity and constraints use it.
+        pub mass: f32,
+        /// Where the particle was one step ago, what `Integrator::Verlet` moves on from.
+        pub previous_position: Vec2,
+        /// Non-zero keeps the particle in place, constraints only move the other end.
+        pub pinned: u32,
+        /// Random 0..1 picked at spawn, what `Flipbook::random_start_frame` starts from.
+        pub frame_offset: f32,
+    }
+}
+
+shader_struct! {
+    /// Per system values shared by all passes, rewritten every frame.
+    pub struct SimulationUniform {
+        /// Maps particle positions to pixels of the rendered texture.
+        pub simulation_to_texture: Mat4,
+        /// Maps world space into the space particles are simulated in.
+        pub world_to_simulation: Mat4,
+        pub simulation_to_world: Mat4,
+        pub texture_to_simulation: Mat4,
+        pub count: u32,
+        /// Dead particles the emit pass respawns this frame.
+        pub emit_count: u32,
+        pub seed: u32,
+        pub frame: u32,
+        pub delta_time: f32,
+        pub elapsed_time: f32,
+        pub alpha: f32,
+        /// See `Boundary::id`.
+        pub boundary: u32,
+        pub restitution: f32,
+        pub friction: f32,
+        /// Whether the update pass collides against the baked obstacle sdf.
+        pub has_obstacles: u32,
+        pub obstacle_restitution: f32,
+        pub obstacle_friction: f32,
+        /// Cells of the neighbor grid, zero when particles don't interact.
+        pub grid_size: UVec2,
+        pub cell_size: f32,
+        pub interaction_radius: f32,
+        pub separation: f32,
+        /// `Boids` parameters, see `Behavior::write_uniform`.
+        pub view_radius: f32,
+        pub separation_radius: f32,
+        pub boids_separation: f32,
+        pub boids_alignment: f32,
+        pub boids_cohesion: f32,
+        pub max_speed: f32,
+        /// `Fluid` parameters.
+        pub kernel_radius: f32,
+        pub rest_density: f32,
+        pub stiffness: f32,
+        pub viscosity: f32,
+        /// `Physarum` parameters.
+        pub sensor_angle: f32,
+        pub sensor_distance: f32,
+        pub turn_speed: f32,
+        pub deposit: f32,
+        pub decay: f32,
+        /// `NBody` parameters.
+        pub gravitational_constant: f32,
+        pub softening: f32,
+        /// See `Integrator::id`.
+        pub integrator: u32,
+        /// Slots at the start of the particle buffer that belong to the constraint points.
+        pub constraint_points: u32,
+        /// See `BlendMode::id`.
+        pub blend_mode: u32,
+        /// `Flipbook` parameters.
+        pub frame_rate: f32,
+        /// See `Playback::id`.
+        pub playback: u32,
+        pub random_start_frame: u32,
+    }
+}
+
+shader_struct! {
+    /// Spawn parameters of a system's `Emitter`, see `Emitter::uniform`.
+    pub struct EmitterUniform {
+        /// Maps the emitter's local canvas space into simulation space.
+        pub transform: Mat4,
+        pub origin: Vec2,
+        pub shape_a: Vec2,
+        pub shape_b: Vec2,
+        pub direction: Vec2,
+        pub lifetime: Vec2,
+        pub speed: Vec2,
+        pub acceleration: Vec2,
+        pub mass: Vec2,
+        pub spread: f32,
+        pub size: f32,
+        pub color: Vec4,
+        pub shape: u32,
+        pub max_alive: u32,
+    }
+}
+
+shader_struct! {
+    /// A `ForceField` in world space, what the fields mean depends on the kind.
+    pub struct ForceFieldData {
+        pub position: Vec2,
+        pub force: Vec2,
+        pub strength: f32,
+        pub radius: f32,
+        pub falloff: f32,
+        pub frequency: f32,
+        pub evolution: f32,
+        pub octaves: u32,
+        pub kind: u32,
+    }
+}
+
+impl WgslType for ForceFieldData {
+    const WGSL: &'static str = "ForceFieldData";
+
+    fn sentinel() -> Self {
+        Self {
+            position: Vec2::sentinel(),
+            ..default()
+        }
+    }
+}
+
+pub const MAX_FORCE_FIELDS: usize = 32;
+
+impl WgslType for [ForceFieldData; MAX_FORCE_FIELDS] {
+    const WGSL: &'static str = "array<ForceFieldData, 32>";
+
+    fn sentinel() -> Self {
+        [ForceFieldData::sentinel(); MAX_FORCE_FIELDS]
+    }
+}
+
+shader_struct! {
+    /// Every force field in the world, shared by all systems.
+    pub struct ForceFields {
+        pub count: u32,
+        pub fields: [ForceFieldData; MAX_FORCE_FIELDS],
+    }
+}
+
+shader_struct! {
+    /// An `ObstacleShape` in canvas pixels, `a` and `b` depend on the kind.
+    pub struct ObstacleShapeData {
+        pub a: Vec2,
+        pub b: Vec2,
+        pub radius: f32,
+        pub kind: u32,
+    }
+}
+
+impl WgslType for ObstacleShapeData {
+    const WGSL: &'static str = "ObstacleShapeData";
+
+    fn sentinel() -> Self {
+        Self {
+            a: Vec2::sentinel(),
+            ..default()
+        }
+    }
+}
+
+pub const MAX_OBSTACLE_SHAPES: usize = 32;
+
+impl WgslType for [ObstacleShapeData; MAX_OBSTACLE_SHAPES] {
+    const WGSL: &'static str = "array<ObstacleShapeData, 32>";
+
+    fn sentinel() -> Self {
+        [ObstacleShapeData::sentinel(); MAX_OBSTACLE_SHAPES]
+    }
+}
+
+shader_struct! {
+    /// Everything a system's obstacle sdf is baked from.
+    pub struct ObstacleShapes {
+        pub count: u32,
+        pub use_mask: u32,
+        pub shapes: [ObstacleShapeData; MAX_OBSTACLE_SHAPES],
+    }
+}
+
+shader_struct! {
+    /// A `DistanceConstraint` between two particle slots, links from a slot to itself are
+    /// padding and skipped.
+    pub struct ConstraintData {
+        pub a: u32,
+        pub b: u32,
+        pub rest_length: f32,
+        pub stiffness: f32,
+    }
+}
+
+shader_struct! {
+    /// A `ConstraintPoint` in the emitter's local canvas space.
+    pub struct ConstraintPointData {
+        pub position: Vec2,
+        pub pinned: u32,
+    }
+}
+
+/// The module every particle shader pulls in with `#import logic_particles::particle`.
+pub fn particle_shader() -> Shader {
+    Shader::from_wgsl(format!(
+        "#define_import_path logic_particles::particle\n\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        Particle::wgsl_struct(),
+        SimulationUniform::wgsl_struct(),
+        EmitterUniform::wgsl_struct(),
+        ForceFieldData::wgsl_struct(),
+        ForceFields::wgsl_struct(),
+        ObstacleShapeData::wgsl_struct(),
+        ObstacleShapes::wgsl_struct(),
+        ConstraintData::wgsl_struct(),
+        ConstraintPointData::wgsl_struct(),
+        helper_functions()
+    ))
+}
+
+/// Functions shared by the particle shaders. The sizes are written in directly, this module
+/// can't import `logic_particles::constants` without the shaders getting it twice.
+fn helper_functions() -> String {
+    format!(
+        "// Index of an invocation in a `run_compute_pass` dispatch
+fn id(invocation_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {{
+    return invocation_id.y * num_workgroups.x * {}u + invocation_id.x;
+}}
+
+// Index of an invocation in a `run_tiled_compute_pass` dispatch
+fn tiled_id(invocation_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {{
+    return invocation_id.y * num_workgroups.x * {}u + invocation_id.x;
+}}
+
+fn is_alive(particle: Particle) -> bool {{
+    return particle.age < particle.lifetime;
+}}
+
+// Where a particle is drawn `seconds` after the last fixed step
+fn extrapolate(particle: Particle, seconds: f32) -> vec2<f32> {{
+    return particle.position + particle.velocity * seconds;
+}}
+",
+        WORKGROUP_SIZE, TILE_SIZE
+    )
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Parses the generated wgsl with naga and compares its layout to what encase writes.
+    pub fn assert_layout_matches<T: ShaderType>(name: &str, wgsl: &str, rust: &[(&str, usize)]) {
+        let module = naga::front::wgsl::parse_str(wgsl).unwrap();
+        let (members, span) = module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match &ty.inner {
+                naga::TypeInner::Struct { members, span } if ty.name.as_deref() == Some(name) => {
+                    Some((members.clone(), *span))
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(span as u64, T::min_size().get(), "size of {}", name);
+        assert_eq!(members.len(), rust.len());
+        for (member, (field, offset)) in members.iter().zip(rust) {
+            assert_eq!(member.name.as_deref(), Some(*field));
+            assert_eq!(
+                member.offset as usize, *offset,
+                "offset of {}.{}",
+                name, field
+            );
+        }
+    }
+
+    #[test]
+    fn particle_layout_matches_wgsl() {
+        assert_layout_matches::<Particle>(
+            "Particle",
+            &Particle::wgsl_struct(),
+            &Particle::field_offsets(),
+        );
+    }
+
+    #[test]
+    fn simulation_uniform_layout_matches_wgsl() {
+        assert_layout_matches::<SimulationUniform>(
+            "SimulationUniform",
+            &SimulationUniform::wgsl_struct(),
+            &SimulationUniform::field_offsets(),
+        );
+    }
+
+    #[test]
+    fn emitter_uniform_layout_matches_wgsl() {
+        assert_layout_matches::<EmitterUniform>(
+            "EmitterUniform",
+            &EmitterUniform::wgsl_struct(),
+            &EmitterUniform::field_offsets(),
+        );
+    }
+
+    #[test]
+    fn force_fields_layout_matches_wgsl() {
+        let wgsl = ForceFieldData::wgsl_struct() + &ForceFields::wgsl_struct();
+        assert_layout_matches::<ForceFieldData>(
+            "ForceFieldData",
+            &wgsl,
+            &ForceFieldData::field_offsets(),
+        );
+        assert_layout_matches::<ForceFields>("ForceFields", &wgsl, &ForceFields::field_offsets());
+    }
+
+    #[test]
+    fn obstacle_shapes_layout_matches_wgsl() {
+        let wgsl = ObstacleShapeData::wgsl_struct() + &ObstacleShapes::wgsl_struct();
+        assert_layout_matches::<ObstacleShapeData>(
+            "ObstacleShapeData",
+            &wgsl,
+            &ObstacleShapeData::field_offsets(),
+        );
+        assert_layout_matches::<ObstacleShapes>(
+            "ObstacleShapes",
+            &wgsl,
+            &ObstacleShapes::field_offsets(),
+        );
+    }
+
+    #[test]
+    fn constraints_layout_matches_wgsl() {
+        assert_layout_matches::<ConstraintData>(
+            "ConstraintData",
+            &ConstraintData::wgsl_struct(),
+            &ConstraintData::field_offsets(),
+        );
+        assert_layout_matches::<ConstraintPointData>(
+            "ConstraintPointData",
+            &ConstraintPointData::wgsl_struct(),
+            &ConstraintPointData::field_offsets(),
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use bevy::{
+    core_pipeline::core_2d::Transparent2d,
+    ecs::system::{
+        lifetimeless::{Read, SQuery, SRes},
+        SystemParamItem,
+    },
+    prelude::*,
+    render::{
+        render_phase::{
+            DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+            TrackedRenderPass,
+        },
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::{BevyDefault, GpuImage},
+        view::{ExtractedView, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
+    },
+    utils::FloatOrd,
+};
+
+use crate::{
+    blend::BlendMode,
+    emitter::EmitterTransform,
+    flipbook::ExtractedFlipbook,
+    particle::{shader_bytes, SimulationUniform},
+    particle_system::ParticleSystemRender,
+    ParticleSystem,
+};
+
+/// How a system's particles end up on screen.
+#[derive(Reflect, FromReflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Instanced quads in the 2d transparent phase, sorted against sprites by the z of the
+    /// system's transform. Needs no `ParticleSystem::rendered_texture`.
+    #[default]
+    Quads,
+    /// Plotted into `ParticleSystem::rendered_texture` by a compute pass, for a sprite to
+    /// show. Physarum systems always plot into the texture, their trail lives there.
+    Texture,
+}
+
+#[derive(Resource)]
+pub struct ParticleQuadsPipeline {
+    shader: Handle<Shader>,
+    view_layout: BindGroupLayout,
+    particles_layout: BindGroupLayout,
+    flipbook_layout: BindGroupLayout,
+}
+
+/// The view bind group, rebuilt every frame since the view uniforms are.
+#[derive(Resource, Default)]
+pub struct ParticleQuadsMeta {
+    view_bind_group: Option<BindGroup>,
+}
+
+impl FromWorld for ParticleQuadsPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: Some(ViewUniform::min_size()),
+                },
+                count: None,
+            }],
+        });
+
+        let particles_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(SimulationUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let flipbook_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        ParticleQuadsPipeline {
+            shader: world.resource::<AssetServer>().load("particle_quads.wgsl"),
+            view_layout,
+            particles_layout,
+            flipbook_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Hash, PartialEq, Eq)]
+pub struct ParticleQuadsPipelineKey {
+    hdr: bool,
+    samples: u32,
+    blend_mode: BlendMode,
+    flipbook: bool,
+}
+
+impl SpecializedRenderPipeline for ParticleQuadsPipeline {
+    type Key = ParticleQuadsPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let format = if key.hdr {
+            ViewTarget::TEXTURE_FORMAT_HDR
+        } else {
+            TextureFormat::bevy_default()
+        };
+
+        let mut shader_defs = Vec::new();
+        if key.blend_mode == BlendMode::Premultiplied {
+            shader_defs.push("PREMULTIPLIED".to_string());
+        }
+        let mut layout = vec![self.view_layout.clone(), self.particles_layout.clone()];
+        if key.flipbook {
+            shader_defs.push("FLIPBOOK".to_string());
+            layout.push(self.flipbook_layout.clone());
+        }
+
+        RenderPipelineDescriptor {
+            label: None,
+            layout: Some(layout),
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                shader_defs: shader_defs.clone(),
+                entry_point: "vertex".into(),
+                buffers: vec![],
+            },
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: Some(key.blend_mode.blend_state()),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: key.samples,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        }
+    }
+}
+
+/// Reads the particles from particle buffer `index`.
+pub fn quads_bind_group(
+    entity: Entity,
+    render_device: &RenderDevice,
+    quads_pipeline: &ParticleQuadsPipeline,
+    particle_system_render: &ParticleSystemRender,
+    index: usize,
+) -> BindGroup {
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &quads_pipeline.particles_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(
+                    particle_system_render.particle_buffers[&entity][index]
+                        .as_entire_buffer_binding(),
+                ),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: particle_system_render.simulation_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+        ],
+    })
+}
+
+/// Samples the sheet of a system's flipbook, with its frames in `frames`.
+pub fn flipbook_bind_group(
+    render_device: &RenderDevice,
+    quads_pipeline: &ParticleQuadsPipeline,
+    image: &GpuImage,
+    frames: &Buffer,
+) -> BindGroup {
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &quads_pipeline.flipbook_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&image.texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(&image.sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: frames.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// The frames of a flipbook as the vertex shader reads them, along with the sheet they were
+/// made from to tell when it changes.
+pub struct FlipbookFrames {
+    pub sheet: ExtractedFlipbook,
+    pub buffer: Buffer,
+}
+
+pub fn create_flipbook_frames(
+    render_device: &RenderDevice,
+    flipbook: &ExtractedFlipbook,
+) -> FlipbookFrames {
+    FlipbookFrames {
+        sheet: flipbook.clone(),
+        buffer: render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: &shader_bytes(&flipbook.frames),
+            usage: BufferUsages::STORAGE,
+        }),
+    }
+}
+
+pub fn queue_particle_quads(
+    render_device: Res<RenderDevice>,
+    draw_functions: Res<DrawFunctions<Transparent2d>>,
+    quads_pipeline: Res<ParticleQuadsPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<ParticleQuadsPipeline>>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    msaa: Res<Msaa>,
+    view_uniforms: Res<ViewUniforms>,
+    mut quads_meta: ResMut<ParticleQuadsMeta>,
+    particle_system_render: Res<ParticleSystemRender>,
+    particle_systems: Query<(Entity, &ParticleSystem, Option<&EmitterTransform>)>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent2d>)>,
+) {
+    let Some(view_binding) = view_uniforms.uniforms.binding() else {
+        return;
+    };
+    quads_meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &quads_pipeline.view_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: view_binding,
+        }],
+    }));
+
+    let draw_function = draw_functions.read().get_id::<DrawParticleQuads>().unwrap();
+
+    for (view, mut phase) in &mut views {
+        for (entity, system, transform) in &particle_systems {
+            if system.render_mode != RenderMode::Quads || system.capacity == 0 {
+                continue;
+            }
+            // flipbook systems wait for their sheet to load
+            let flipbook = system.flipbook.is_some();
+            if flipbook
+                && !particle_system_render
+                    .flipbook_bind_groups
+                    .contains_key(&entity)
+            {
+                continue;
+            }
+            let pipeline = pipelines.specialize(
+                &mut pipeline_cache,
+                &quads_pipeline,
+                ParticleQuadsPipelineKey {
+                    hdr: view.hdr,
+                    samples: msaa.samples,
+                    blend_mode: system.blend_mode,
+                    flipbook,
+                },
+            );
+            let z = transform.map_or(0.0, |transform| transform.0.translation().z);
+            phase.add(Transparent2d {
+                sort_key: FloatOrd(z),
+                entity,
+                pipeline,
+                draw_function,
+                batch_range: None,
+            });
+        }
+    }
+}
+
+pub type DrawParticleQuads = (
+    SetItemPipeline,
+    SetQuadsViewBindGroup<0>,
+    SetQuadsParticlesBindGroup<1>,
+    SetQuadsFlipbookBindGroup<2>,
+    DrawQuads,
+);
+
+pub struct SetQuadsViewBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetQuadsViewBindGroup<I> {
+    type Param = (SRes<ParticleQuadsMeta>, SQuery<Read<ViewUniformOffset>>);
+
+    fn render<'w>(
+        view: Entity,
+        _item: Entity,
+        (quads_meta, view_query): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Ok(view_uniform) = view_query.get(view) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(view_bind_group) = quads_meta.into_inner().view_bind_group.as_ref() else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, view_bind_group, &[view_uniform.offset]);
+        RenderCommandResult::Success
+    }
+}
+
+pub struct SetQuadsParticlesBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetQuadsParticlesBindGroup<I> {
+    type Param = SRes<ParticleSystemRender>;
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        particle_system_render: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let particle_system_render = particle_system_render.into_inner();
+        let Some(bind_groups) = particle_system_render.quads_bind_groups.get(&item) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(
+            I,
+            &bind_groups[particle_system_render.current_buffer(item)],
+            &[],
+        );
+        RenderCommandResult::Success
+    }
+}
+
+pub struct SetQuadsFlipbookBindGroup<const I: usize>;
+
+impl<const I: usize> EntityRenderCommand for SetQuadsFlipbookBindGroup<I> {
+    type Param = (SRes<ParticleSystemRender>, SQuery<Read<ParticleSystem>>);
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        (particle_system_render, particle_systems): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Ok(system) = particle_systems.get(item) else {
+            return RenderCommandResult::Failure;
+        };
+        // only flipbook pipelines have the group
+        if system.flipbook.is_none() {
+            return RenderCommandResult::Success;
+        }
+        let particle_system_render = particle_system_render.into_inner();
+        let Some(bind_group) = particle_system_render.flipbook_bind_groups.get(&item) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+pub struct DrawQuads;
+
+impl EntityRenderCommand for DrawQuads {
+    type Param = SQuery<Read<ParticleSystem>>;
+
+    fn render<'w>(
+        _view: Entity,
+        item: Entity,
+        particle_systems: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Ok(system) = particle_systems.get(item) else {
+            return RenderCommandResult::Failure;
+        };
+        // six vertices per quad, dead particles collapse theirs
+        pass.draw(0..6, 0..system.capacity);
+        RenderCommandResult::Success
+    }
+}
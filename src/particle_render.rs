@@ -1,11 +1,15 @@
+use crate::behavior::Behavior;
 use crate::compute_utils::{compute_pipeline_descriptor, run_compute_pass, run_compute_pass_2d};
-use crate::particle_system::ParticleSystemRender;
+use crate::particle::SimulationUniform;
+use crate::particle_quads::RenderMode;
+use crate::particle_system::{ParticleSystemRender, SimulationTime};
 
-use crate::ParticleSystem;
+use crate::{ParticleSystem, HEIGHT, WIDTH};
 use bevy::render::texture::GpuImage;
 use bevy::{
     prelude::*,
     render::{
+        render_asset::RenderAssets,
         render_graph::{self},
         render_resource::*,
         renderer::{RenderContext, RenderDevice},
@@ -16,12 +20,18 @@ use bevy::{
 #[derive(Resource, Clone)]
 pub struct ParticleRenderPipeline {
     bind_group_layout: BindGroupLayout,
+    trail_bind_group_layout: BindGroupLayout,
     clear_pipeline: CachedComputePipelineId,
     render_pipeline: CachedComputePipelineId,
+    resolve_pipeline: CachedComputePipelineId,
+    diffuse_pipeline: CachedComputePipelineId,
+    deposit_pipeline: CachedComputePipelineId,
+    /// Bound in place of the accumulation buffer by systems that don't have one.
+    empty_accumulation: Buffer,
 }
 
 pub struct RenderParticlesNode {
-    particle_systems: QueryState<Entity, With<ParticleSystem>>,
+    particle_systems: QueryState<(Entity, &'static ParticleSystem)>,
     render_state: HashMap<Entity, ParticleRenderState>,
 }
 
@@ -56,16 +66,147 @@ fn bind_group_layout() -> BindGroupLayoutDescriptor<'static> {
                 },
                 count: None,
             },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(SimulationUniform::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    }
+}
+
+fn trail_bind_group_layout() -> BindGroupLayoutDescriptor<'static> {
+    BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::ReadWrite,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(SimulationUniform::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_dimension: TextureViewDimension::D2,
+                },
+                count: None,
+            },
         ],
     }
 }
 
+const TRAIL_SIZE: Extent3d = Extent3d {
+    width: WIDTH as u32,
+    height: HEIGHT as u32,
+    depth_or_array_layers: 1,
+};
+
+/// Scratch texture the trail of a physarum system is blurred into.
+pub fn create_trail_texture(render_device: &RenderDevice) -> Texture {
+    render_device.create_texture(&TextureDescriptor {
+        label: None,
+        size: TRAIL_SIZE,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC,
+    })
+}
+
+/// Whether a system draws into `ParticleSystem::rendered_texture`, the others don't need one.
+pub fn plots(system: &ParticleSystem) -> bool {
+    system.render_mode == RenderMode::Texture || matches!(system.behavior, Behavior::Physarum(_))
+}
+
+/// Whether a system draws through the accumulation buffer, physarum plots into its trail.
+pub fn accumulates(system: &ParticleSystem) -> bool {
+    system.render_mode == RenderMode::Texture && !matches!(system.behavior, Behavior::Physarum(_))
+}
+
+/// Fixed point color and weight sums, four `u32` per pixel, see `BlendMode`.
+pub fn create_accumulation_buffer(render_device: &RenderDevice) -> Buffer {
+    render_device.create_buffer(&BufferDescriptor {
+        label: None,
+        size: (TRAIL_SIZE.width * TRAIL_SIZE.height) as u64 * 4 * std::mem::size_of::<u32>() as u64,
+        usage: BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+pub fn trail_bind_group(
+    entity: Entity,
+    render_device: &RenderDevice,
+    render_pipeline: &ParticleRenderPipeline,
+    particle_system_render: &ParticleSystemRender,
+    view: &GpuImage,
+) -> BindGroup {
+    let scratch = particle_system_render.trail_textures[&entity]
+        .create_view(&TextureViewDescriptor::default());
+
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &render_pipeline.trail_bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&view.texture_view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: particle_system_render.simulation_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::TextureView(&scratch),
+            },
+        ],
+    })
+}
+
+/// Draws the particles in particle buffer `index`.
 pub fn render_bind_group(
     entity: Entity,
     render_device: &RenderDevice,
     render_pipeline: &ParticleRenderPipeline,
     particle_system_render: &ParticleSystemRender,
     view: &GpuImage,
+    index: usize,
 ) -> BindGroup {
     render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
@@ -74,41 +215,95 @@ pub fn render_bind_group(
             BindGroupEntry {
                 binding: 0,
                 resource: BindingResource::Buffer(
-                    particle_system_render.particle_buffers[&entity].as_entire_buffer_binding(),
+                    particle_system_render.particle_buffers[&entity][index]
+                        .as_entire_buffer_binding(),
                 ),
             },
             BindGroupEntry {
                 binding: 1,
                 resource: BindingResource::TextureView(&view.texture_view),
             },
+            BindGroupEntry {
+                binding: 2,
+                resource: particle_system_render.simulation_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::Buffer(
+                    particle_system_render
+                        .accumulation_buffers
+                        .get(&entity)
+                        .unwrap_or(&render_pipeline.empty_accumulation)
+                        .as_entire_buffer_binding(),
+                ),
+            },
         ],
     })
 }
 
 impl FromWorld for ParticleRenderPipeline {
     fn from_world(world: &mut World) -> Self {
-        let bind_group_layout = world
-            .resource::<RenderDevice>()
-            .create_bind_group_layout(&bind_group_layout());
-        let shader = world.resource::<AssetServer>().load("particle_render.wgsl");
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(&bind_group_layout());
+        let trail_bind_group_layout =
+            render_device.create_bind_group_layout(&trail_bind_group_layout());
+        let shader = world
+            .resource::<AssetServer>()
+            .load("particle_render.compute.wgsl");
+        let empty_accumulation = render_device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 4 * std::mem::size_of::<u32>() as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
         let mut pipeline_cache = world.resource_mut::<PipelineCache>();
 
         let render_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
             shader.clone(),
             "render",
             &bind_group_layout,
+            vec![],
+        ));
+
+        let resolve_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader.clone(),
+            "resolve",
+            &bind_group_layout,
+            vec![],
         ));
 
         let clear_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
-            shader,
+            shader.clone(),
             "clear",
             &bind_group_layout,
+            vec![],
+        ));
+
+        let diffuse_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader.clone(),
+            "diffuse",
+            &trail_bind_group_layout,
+            vec![],
+        ));
+
+        let deposit_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader,
+            "render",
+            &bind_group_layout,
+            vec!["PHYSARUM".to_string()],
         ));
 
         ParticleRenderPipeline {
             bind_group_layout,
+            trail_bind_group_layout,
             clear_pipeline,
             render_pipeline,
+            resolve_pipeline,
+            diffuse_pipeline,
+            deposit_pipeline,
+            empty_accumulation,
         }
     }
 }
@@ -135,25 +330,70 @@ impl render_graph::Node for RenderParticlesNode {
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<ParticleRenderPipeline>();
         let particle_systems_render = world.resource::<ParticleSystemRender>();
+        let time = world.resource::<SimulationTime>();
 
-        for entity in self.particle_systems.iter_manual(world) {
-            if let Some((clear_pipeline, render_pipeline)) = match self.render_state[&entity] {
-                ParticleRenderState::Loading => None,
-                ParticleRenderState::Render => {
-                    Some((pipeline.clear_pipeline, pipeline.render_pipeline))
+        for (entity, system) in self.particle_systems.iter_manual(world) {
+            if let ParticleRenderState::Loading = self.render_state[&entity] {
+                continue;
+            }
+            if !plots(system) {
+                continue;
+            }
+            // empty systems have nothing to draw with
+            let Some(bind_groups) = particle_systems_render.render_bind_group.get(&entity) else {
+                continue;
+            };
+            let bind_group = &bind_groups[particle_systems_render.current_buffer(entity)];
+
+            // physarum keeps its trail around, blurred and faded instead of cleared
+            let trail_bind_group = match system.behavior {
+                Behavior::Physarum(_) => particle_systems_render.trail_bind_groups.get(&entity),
+                _ => None,
+            };
+            let gpu_images = world.resource::<RenderAssets<Image>>();
+            let texture = gpu_images.get(&system.rendered_texture);
+            if let Some((trail_bind_group, texture)) = trail_bind_group.zip(texture) {
+                // once per simulation step like the agents, so the trail doesn't depend on the
+                // frame rate
+                for _ in 0..time.steps {
+                    run_compute_pass_2d(
+                        render_context,
+                        trail_bind_group,
+                        pipeline_cache,
+                        pipeline.diffuse_pipeline,
+                    );
+                    render_context.command_encoder.copy_texture_to_texture(
+                        particle_systems_render.trail_textures[&entity].as_image_copy(),
+                        texture.texture.as_image_copy(),
+                        TRAIL_SIZE,
+                    );
+                    run_compute_pass(
+                        render_context,
+                        bind_group,
+                        pipeline_cache,
+                        pipeline.deposit_pipeline,
+                        system.capacity,
+                    );
                 }
-            } {
+            } else {
                 run_compute_pass_2d(
                     render_context,
-                    &particle_systems_render.render_bind_group[&entity],
+                    bind_group,
                     pipeline_cache,
-                    clear_pipeline,
+                    pipeline.clear_pipeline,
                 );
                 run_compute_pass(
                     render_context,
-                    &particle_systems_render.render_bind_group[&entity],
+                    bind_group,
+                    pipeline_cache,
+                    pipeline.render_pipeline,
+                    system.capacity,
+                );
+                run_compute_pass_2d(
+                    render_context,
+                    bind_group,
                     pipeline_cache,
-                    render_pipeline,
+                    pipeline.resolve_pipeline,
                 );
             }
         }
@@ -185,9 +425,19 @@ impl RenderParticlesNode {
         };
         match render_state {
             ParticleRenderState::Loading => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.render_pipeline)
-                {
+                let pipelines = [
+                    pipeline.clear_pipeline,
+                    pipeline.render_pipeline,
+                    pipeline.resolve_pipeline,
+                    pipeline.diffuse_pipeline,
+                    pipeline.deposit_pipeline,
+                ];
+                if pipelines.iter().all(|id| {
+                    matches!(
+                        pipeline_cache.get_compute_pipeline_state(*id),
+                        CachedPipelineState::Ok(_)
+                    )
+                }) {
                     self.render_state
                         .insert(entity, ParticleRenderState::Render);
                 }
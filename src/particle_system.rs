@@ -1,17 +1,52 @@
-use crate::particle_render::{render_bind_group, ParticleRenderPipeline, RenderParticlesNode};
+use crate::behavior::{Behavior, Boids, Fluid, NBody, Physarum};
+use crate::blend::BlendMode;
+use crate::boundary::Boundary;
+use crate::compute_utils::{constants_shader, ComputeShaderLoader, CONSTANTS_SHADER_HANDLE};
+use crate::constraint::{
+    constraint_bind_groups, ConstraintBuffers, ConstraintPipeline, ConstraintPoint, Constraints,
+    DistanceConstraint,
+};
+use crate::emitter::{
+    add_emitter_state, tick_emitters, Burst, Emitter, EmitterShape, EmitterState, EmitterTransform,
+    SimulationSpace, SpaceTransforms,
+};
+use crate::flipbook::{extract_flipbooks, ExtractedFlipbooks, Flipbook, Playback, SpriteSheet};
+use crate::force_field::{extract_force_fields, ExtractedForceFields, ForceField};
+use crate::integrator::Integrator;
+use crate::nbody::{nbody_bind_group, NBodyPipeline};
+use crate::obstacle::{
+    create_sdf_texture, obstacle_bind_group, ObstaclePipeline, ObstacleShape, Obstacles,
+};
+use crate::particle::{
+    particle_shader, EmitterUniform, ForceFields, ObstacleShapes, Particle, SimulationUniform,
+    PARTICLE_SHADER_HANDLE,
+};
+use crate::particle_quads::{
+    create_flipbook_frames, flipbook_bind_group, quads_bind_group, queue_particle_quads,
+    DrawParticleQuads, FlipbookFrames, ParticleQuadsMeta, ParticleQuadsPipeline, RenderMode,
+};
+use crate::particle_render::{
+    accumulates, create_accumulation_buffer, create_trail_texture, plots, render_bind_group,
+    trail_bind_group, ParticleRenderPipeline, RenderParticlesNode,
+};
 use crate::particle_update::{update_bind_group, ParticleUpdatePipeline, UpdateParticlesNode};
-use crate::{Particle, ParticleSystem, PARTICLE_COUNT};
+use crate::spatial_grid::{grid_bind_group, Interaction, SpatialGrid, SpatialGridPipeline};
+use crate::ParticleSystem;
 use bevy::{
+    core_pipeline::core_2d::Transparent2d,
     prelude::*,
     render::{
         extract_component::{ExtractComponent, ExtractComponentPlugin},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
         render_asset::RenderAssets,
         render_graph::RenderGraph,
+        render_phase::AddRenderCommand,
         render_resource::*,
-        renderer::RenderDevice,
+        renderer::{RenderDevice, RenderQueue},
+        texture::{FallbackImage, GpuImage},
         RenderApp, RenderStage,
     },
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 
 pub struct ParticlePlugin;
@@ -19,21 +54,243 @@ pub struct ParticlePlugin;
 // Must maintain all our own data because render world flushes between frames :,(
 #[derive(Resource, Default)]
 pub struct ParticleSystemRender {
-    pub update_bind_group: HashMap<Entity, BindGroup>,
-    pub render_bind_group: HashMap<Entity, BindGroup>,
-    pub particle_buffers: HashMap<Entity, Buffer>,
+    /// Bind groups that bind the particle buffers come in pairs, see `update_bind_group` and
+    /// the others for which buffer each one reads or writes.
+    pub update_bind_group: HashMap<Entity, [BindGroup; 2]>,
+    /// The spawn mask and trail the update bind groups were built with.
+    pub update_bind_group_images: HashMap<Entity, [BoundImage; 2]>,
+    pub render_bind_group: HashMap<Entity, [BindGroup; 2]>,
+    pub quads_bind_groups: HashMap<Entity, [BindGroup; 2]>,
+    /// Two copies of the particle state, every update step reads one and writes the other.
+    pub particle_buffers: HashMap<Entity, [Buffer; 2]>,
+    /// Which particle buffer holds the state after this frame's steps.
+    pub current_buffers: HashMap<Entity, usize>,
+    /// What the buffers were allocated for, a different `ParticleSystem::capacity` reallocates.
+    pub capacities: HashMap<Entity, u32>,
+    /// Systems reallocated since the update node last looked, it starts them over from init.
+    pub reallocated: HashSet<Entity>,
+    /// Particles due to spawn that the emit pass hasn't run for yet, while the pipelines load.
+    pub pending_emits: HashMap<Entity, u32>,
+    pub dead_list_buffers: HashMap<Entity, Buffer>,
+    pub simulation_uniforms: HashMap<Entity, UniformBuffer<SimulationUniform>>,
+    pub emitter_uniforms: HashMap<Entity, UniformBuffer<EmitterUniform>>,
+    /// Fixed size, so bind groups never have to be rebuilt for it.
+    pub force_fields: HashMap<Entity, StorageBuffer<ForceFields>>,
+    pub obstacle_shapes: HashMap<Entity, StorageBuffer<ObstacleShapes>>,
+    /// Only created for systems with obstacles.
+    pub sdf_textures: HashMap<Entity, Texture>,
+    /// Along with the mask they were built with.
+    pub obstacle_bind_groups: HashMap<Entity, (BoundImage, BindGroup)>,
+    /// Per particle forces from its neighbors and n-body gravity, zero for systems without either.
+    pub interaction_buffers: HashMap<Entity, Buffer>,
+    pub grids: HashMap<Entity, SpatialGrid>,
+    pub grid_bind_groups: HashMap<Entity, [BindGroup; 2]>,
+    /// Where the trail of physarum systems is blurred into before it is copied back.
+    pub trail_textures: HashMap<Entity, Texture>,
+    pub trail_bind_groups: HashMap<Entity, BindGroup>,
+    /// What `RenderMode::Texture` blends particles in before they are resolved to the texture,
+    /// only created for systems where `accumulates` holds.
+    pub accumulation_buffers: HashMap<Entity, Buffer>,
+    /// Only kept for flipbook systems while their sheet is loaded.
+    pub flipbook_frames: HashMap<Entity, FlipbookFrames>,
+    pub flipbook_bind_groups: HashMap<Entity, BindGroup>,
+    pub nbody_bind_groups: HashMap<Entity, [BindGroup; 2]>,
+    pub constraints: HashMap<Entity, ConstraintBuffers>,
+    /// One per batch of links, with the number of links in it.
+    pub constraint_bind_groups: HashMap<Entity, [Vec<(BindGroup, u32)>; 2]>,
+    /// Systems whose constraint bind groups were built since the update node last placed
+    /// their points.
+    pub unplaced_constraints: HashSet<Entity>,
+}
+
+/// Which image a bind group was built with, `None` being the fallback image. Any other
+/// handle or size means the bind group has to be built again.
+#[derive(Clone, PartialEq)]
+pub struct BoundImage {
+    handle: Option<Handle<Image>>,
+    size: Vec2,
+}
+
+/// Looks up an optional image, `None` stands for the fallback image. Nothing is returned
+/// while the image is loading.
+fn bound_image<'a>(
+    handle: Option<&Handle<Image>>,
+    gpu_images: &'a RenderAssets<Image>,
+    fallback_image: &'a FallbackImage,
+) -> Option<(BoundImage, &'a GpuImage)> {
+    let image = match handle {
+        Some(handle) => gpu_images.get(handle)?,
+        None => &**fallback_image,
+    };
+    let bound = BoundImage {
+        handle: handle.map(Handle::clone_weak),
+        size: image.size,
+    };
+    Some((bound, image))
+}
+
+impl ParticleSystemRender {
+    /// Particle buffer the last update step wrote to, the one rendering reads.
+    pub fn current_buffer(&self, entity: Entity) -> usize {
+        self.current_buffers
+            .get(&entity)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Drops everything sized by the capacity, the queue system builds it again. Grids are
+    /// rebuilt by `prepare_simulation_uniforms` already.
+    fn release_buffers(&mut self, entity: Entity) {
+        self.particle_buffers.remove(&entity);
+        self.current_buffers.remove(&entity);
+        self.capacities.remove(&entity);
+        self.dead_list_buffers.remove(&entity);
+        self.interaction_buffers.remove(&entity);
+        self.constraints.remove(&entity);
+        self.update_bind_group.remove(&entity);
+        self.render_bind_group.remove(&entity);
+        self.quads_bind_groups.remove(&entity);
+        self.grid_bind_groups.remove(&entity);
+        self.nbody_bind_groups.remove(&entity);
+        self.constraint_bind_groups.remove(&entity);
+        self.reallocated.insert(entity);
+    }
+
+    /// The update pass adds the interaction forces every step, once the passes writing them
+    /// stop running they have to be zeroed or the last ones stick around.
+    fn clear_interaction_forces(&self, entity: Entity, render_queue: &RenderQueue) {
+        if let Some(buffer) = self.interaction_buffers.get(&entity) {
+            render_queue.write_buffer(buffer, 0, &vec![0; buffer.size() as usize]);
+        }
+    }
+}
+
+/// How the simulation advances, independent of the frame rate.
+#[derive(Resource, Clone)]
+pub struct ParticleTimestep {
+    /// Length of one simulation step in seconds, 0 or less pauses the simulation.
+    pub step_seconds: f32,
+    /// Steps run per frame at most, time beyond that is dropped instead of catching up.
+    pub max_steps: u32,
+}
+
+impl Default for ParticleTimestep {
+    fn default() -> Self {
+        Self {
+            step_seconds: 1.0 / 60.0,
+            max_steps: 4,
+        }
+    }
+}
+
+/// Frame timing handed to the shaders, tracked in the main world and extracted every frame.
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct SimulationTime {
+    /// Fixed step length, the update pass is run `steps` times with it.
+    pub delta_seconds: f32,
+    /// Simulated time, advanced only by whole steps.
+    pub elapsed_seconds: f32,
+    pub frame: u32,
+    pub steps: u32,
+    /// How far real time is between the last step and the next one, for the render pass.
+    pub alpha: f32,
+    accumulator: f32,
+}
+
+impl SimulationTime {
+    /// Counts the whole steps that fit into the real time passed, carrying the rest over.
+    fn advance(&mut self, delta_seconds: f32, timestep: &ParticleTimestep) {
+        let step = timestep.step_seconds;
+        self.frame = self.frame.wrapping_add(1);
+        // no number of such steps ever catches up with real time
+        if step <= 0.0 || step.is_nan() {
+            self.steps = 0;
+            self.alpha = 0.0;
+            return;
+        }
+        self.accumulator += delta_seconds;
+
+        let steps = (self.accumulator / step).floor();
+        self.accumulator -= steps * step;
+        self.steps = (steps as u32).min(timestep.max_steps);
+
+        self.delta_seconds = step;
+        self.elapsed_seconds += self.steps as f32 * step;
+        self.alpha = self.accumulator / step;
+    }
 }
 
 impl Plugin for ParticlePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(ExtractComponentPlugin::<ParticleSystem>::default());
+        app.init_resource::<ParticleTimestep>()
+            .init_resource::<SimulationTime>()
+            .register_type::<ParticleSystem>()
+            .register_type::<RenderMode>()
+            .register_type::<BlendMode>()
+            .register_type::<Flipbook>()
+            .register_type::<Option<Flipbook>>()
+            .register_type::<SpriteSheet>()
+            .register_type::<Playback>()
+            .register_type::<ForceField>()
+            .register_type::<Vec<ForceField>>()
+            .register_type::<Emitter>()
+            .register_type::<EmitterShape>()
+            .register_type::<Burst>()
+            .register_type::<Vec<Burst>>()
+            .register_type::<SimulationSpace>()
+            .register_type::<Boundary>()
+            .register_type::<Obstacles>()
+            .register_type::<ObstacleShape>()
+            .register_type::<Vec<ObstacleShape>>()
+            .register_type::<Interaction>()
+            .register_type::<Option<Interaction>>()
+            .register_type::<Behavior>()
+            .register_type::<Boids>()
+            .register_type::<Fluid>()
+            .register_type::<Physarum>()
+            .register_type::<NBody>()
+            .register_type::<Integrator>()
+            .register_type::<Constraints>()
+            .register_type::<Option<Constraints>>()
+            .register_type::<ConstraintPoint>()
+            .register_type::<Vec<ConstraintPoint>>()
+            .register_type::<DistanceConstraint>()
+            .register_type::<Vec<DistanceConstraint>>()
+            .add_system(limit_capacity)
+            .add_system(update_simulation_time)
+            .add_system(add_emitter_state)
+            .add_system(tick_emitters.after(update_simulation_time))
+            .add_plugin(ExtractComponentPlugin::<ParticleSystem>::default())
+            .add_plugin(ExtractComponentPlugin::<EmitterState>::default())
+            .add_plugin(ExtractComponentPlugin::<EmitterTransform>::default())
+            .add_plugin(ExtractResourcePlugin::<SimulationTime>::default())
+            // before the pipelines below start loading their shaders
+            .init_asset_loader::<ComputeShaderLoader>();
+
+        let mut shaders = app.world.resource_mut::<Assets<Shader>>();
+        shaders.set_untracked(PARTICLE_SHADER_HANDLE, particle_shader());
+        shaders.set_untracked(CONSTANTS_SHADER_HANDLE, constants_shader());
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app
             .init_resource::<ParticleUpdatePipeline>()
             .init_resource::<ParticleSystemRender>()
             .init_resource::<ParticleRenderPipeline>()
-            .add_system_to_stage(RenderStage::Queue, queue_bind_group);
+            .init_resource::<ObstaclePipeline>()
+            .init_resource::<SpatialGridPipeline>()
+            .init_resource::<NBodyPipeline>()
+            .init_resource::<ConstraintPipeline>()
+            .init_resource::<ParticleQuadsPipeline>()
+            .init_resource::<SpecializedRenderPipelines<ParticleQuadsPipeline>>()
+            .init_resource::<ParticleQuadsMeta>()
+            .add_render_command::<Transparent2d, DrawParticleQuads>()
+            .init_resource::<ExtractedForceFields>()
+            .add_system_to_stage(RenderStage::Extract, extract_force_fields)
+            .init_resource::<ExtractedFlipbooks>()
+            .add_system_to_stage(RenderStage::Extract, extract_flipbooks)
+            .add_system_to_stage(RenderStage::Prepare, prepare_simulation_uniforms)
+            .add_system_to_stage(RenderStage::Queue, queue_bind_group)
+            .add_system_to_stage(RenderStage::Queue, queue_particle_quads);
 
         let update_node = UpdateParticlesNode::new(&mut render_app.world);
         let render_node = RenderParticlesNode::new(&mut render_app.world);
@@ -55,78 +312,529 @@ impl Plugin for ParticlePlugin {
     }
 }
 
+/// Particles are bound as a single storage buffer, so a system can't hold more of them than
+/// the device lets one binding see.
+fn limit_capacity(
+    render_device: Res<RenderDevice>,
+    mut systems: Query<(Entity, &mut ParticleSystem), Changed<ParticleSystem>>,
+) {
+    let max_binding_size = render_device.limits().max_storage_buffer_binding_size as u64;
+    let max_capacity = (max_binding_size / Particle::SHADER_SIZE.get()) as u32;
+    for (entity, mut system) in &mut systems {
+        if system.capacity > max_capacity {
+            error!(
+                "{:?} asks for {} particles, but this device only binds {} of them, clamping",
+                entity, system.capacity, max_capacity
+            );
+            system.capacity = max_capacity;
+        }
+    }
+}
+
+fn update_simulation_time(
+    time: Res<Time>,
+    timestep: Res<ParticleTimestep>,
+    mut simulation_time: ResMut<SimulationTime>,
+) {
+    simulation_time.advance(time.delta_seconds(), &timestep);
+}
+
+fn prepare_simulation_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    time: Res<SimulationTime>,
+    force_fields: Res<ExtractedForceFields>,
+    mut particle_system_render: ResMut<ParticleSystemRender>,
+    particle_systems: Query<(
+        Entity,
+        &ParticleSystem,
+        Option<&EmitterState>,
+        Option<&EmitterTransform>,
+    )>,
+) {
+    for (entity, system, emitter_state, transform) in &particle_systems {
+        // nothing is allocated for empty systems
+        if system.capacity == 0 {
+            continue;
+        }
+        let transform = transform.map_or(GlobalTransform::IDENTITY, |transform| transform.0);
+        let spaces = SpaceTransforms::new(system.simulation_space, &transform);
+
+        let (restitution, friction) = system.boundary.bounce();
+
+        // cleared by the update node once the emit pass runs
+        let pending = particle_system_render
+            .pending_emits
+            .entry(entity)
+            .or_default();
+        *pending = pending
+            .saturating_add(emitter_state.map_or(0, |state| state.to_emit))
+            .min(system.capacity);
+        let emit_count = *pending;
+
+        // radii may be edited at any time, cells smaller than them would miss neighbors
+        match system.neighbor_radius() {
+            Some(radius) => {
+                let fits = particle_system_render
+                    .grids
+                    .get(&entity)
+                    .map_or(false, |grid| grid.fits(radius, system.capacity));
+                if !fits {
+                    let grid = SpatialGrid::new(&render_device, radius, system.capacity);
+                    particle_system_render.grids.insert(entity, grid);
+                    particle_system_render.grid_bind_groups.remove(&entity);
+                }
+            }
+            None => {
+                if particle_system_render.grids.remove(&entity).is_some() {
+                    particle_system_render.grid_bind_groups.remove(&entity);
+                    particle_system_render.clear_interaction_forces(entity, &render_queue);
+                }
+            }
+        }
+        let grid = particle_system_render.grids.get(&entity);
+        let (grid_size, cell_size) =
+            grid.map_or((UVec2::ZERO, 0.0), |grid| (grid.cells, grid.cell_size));
+        let (interaction_radius, separation) = match &system.interaction {
+            // capped to the cell size like the boids radii in `Behavior::write_uniform`
+            Some(interaction) => (interaction.radius.min(cell_size), interaction.separation),
+            None => (0.0, 0.0),
+        };
+
+        let mut simulation = SimulationUniform {
+            simulation_to_texture: spaces.simulation_to_texture,
+            world_to_simulation: spaces.world_to_simulation,
+            simulation_to_world: spaces.world_to_simulation.inverse(),
+            texture_to_simulation: spaces.simulation_to_texture.inverse(),
+            count: system.capacity,
+            emit_count,
+            // mix in the entity so systems sharing a seed still spawn differently
+            seed: system.seed ^ entity.index().wrapping_mul(0x9e37_79b9),
+            frame: time.frame,
+            delta_time: time.delta_seconds,
+            elapsed_time: time.elapsed_seconds,
+            alpha: time.alpha,
+            boundary: system.boundary.id(),
+            restitution,
+            friction,
+            has_obstacles: !system.obstacles.is_empty() as u32,
+            obstacle_restitution: system.obstacles.restitution,
+            obstacle_friction: system.obstacles.friction,
+            grid_size,
+            cell_size,
+            interaction_radius,
+            separation,
+            integrator: system.integrator.id(),
+            constraint_points: system
+                .constraints
+                .as_ref()
+                .map_or(0, |constraints| constraints.point_count(system.capacity)),
+            blend_mode: system.blend_mode.id(),
+            ..default()
+        };
+        system.behavior.write_uniform(&mut simulation);
+        if let Some(flipbook) = &system.flipbook {
+            flipbook.write_uniform(&mut simulation);
+        }
+
+        let uniform = particle_system_render
+            .simulation_uniforms
+            .entry(entity)
+            .or_default();
+        uniform.set(simulation);
+        uniform.write_buffer(&render_device, &render_queue);
+
+        let uniform = particle_system_render
+            .emitter_uniforms
+            .entry(entity)
+            .or_default();
+        uniform.set(system.emitter.uniform(spaces.emitter_to_simulation));
+        uniform.write_buffer(&render_device, &render_queue);
+
+        let fields = particle_system_render
+            .force_fields
+            .entry(entity)
+            .or_default();
+        fields.set(force_fields.with_local(&system.force_fields, &transform));
+        fields.write_buffer(&render_device, &render_queue);
+
+        if !system.obstacles.is_empty() {
+            let shapes = particle_system_render
+                .obstacle_shapes
+                .entry(entity)
+                .or_default();
+            shapes.set(system.obstacles.shapes());
+            shapes.write_buffer(&render_device, &render_queue);
+        }
+    }
+}
+
 fn queue_bind_group(
     render_device: Res<RenderDevice>,
-    //render_queue: Res<RenderQueue>,
+    render_queue: Res<RenderQueue>,
     render_pipeline: Res<ParticleRenderPipeline>,
     gpu_images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
     mut particle_system_render: ResMut<ParticleSystemRender>,
     update_pipeline: Res<ParticleUpdatePipeline>,
+    obstacle_pipeline: Res<ObstaclePipeline>,
+    grid_pipeline: Res<SpatialGridPipeline>,
+    nbody_pipeline: Res<NBodyPipeline>,
+    constraint_pipeline: Res<ConstraintPipeline>,
+    quads_pipeline: Res<ParticleQuadsPipeline>,
+    flipbooks: Res<ExtractedFlipbooks>,
     //Getting mutable queries in the render world is an antipattern?
     particle_systems: Query<(Entity, &ParticleSystem)>,
 ) {
     // Everything here is done lazily and should only happen on the first call here.
     for (entity, system) in &particle_systems {
+        let allocated = particle_system_render.capacities.get(&entity).copied();
+        if allocated.map_or(false, |capacity| capacity != system.capacity) {
+            particle_system_render.release_buffers(entity);
+        }
+        // zero sized buffers don't pass validation
+        if system.capacity == 0 {
+            continue;
+        }
+
         if !particle_system_render
             .particle_buffers
             .contains_key(&entity)
         {
-            let particle = [Particle::default(); PARTICLE_COUNT as usize];
-            //ugh
-            let mut byte_buffer = Vec::new();
-            let mut buffer = encase::StorageBuffer::new(&mut byte_buffer);
-            buffer.write(&particle).unwrap();
-
-            let storage = render_device.create_buffer_with_data(&BufferInitDescriptor {
-                label: None,
-                usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-                contents: buffer.into_inner(),
+            // wgpu zero initializes buffers, which is what Particle::default() is anyway
+            let storage = [(); 2].map(|_| {
+                render_device.create_buffer(&BufferDescriptor {
+                    label: None,
+                    size: Particle::SHADER_SIZE.get() * system.capacity as u64,
+                    usage: BufferUsages::COPY_DST | BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                })
             });
 
             particle_system_render
                 .particle_buffers
                 .insert(entity, storage);
+            particle_system_render
+                .capacities
+                .insert(entity, system.capacity);
+
+            // an atomic count followed by one index per particle, filled by the init pass
+            let dead_list = render_device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: 4 + 4 * system.capacity as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+
+            particle_system_render
+                .dead_list_buffers
+                .insert(entity, dead_list);
+
+            let interaction = render_device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: 8 * system.capacity as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            particle_system_render
+                .interaction_buffers
+                .insert(entity, interaction);
         }
 
         /*
         read_buffer(
-            &particle_systems_render.particle_buffers[&entity],
+            &particle_systems_render.particle_buffers[&entity][0],
             &render_device,
             &render_queue,
         );
         */
 
+        if system.obstacles.is_empty() {
+            // nothing to collide with, the bake pass stops along with the bind group
+            if particle_system_render
+                .sdf_textures
+                .remove(&entity)
+                .is_some()
+            {
+                particle_system_render.obstacle_bind_groups.remove(&entity);
+                particle_system_render.obstacle_shapes.remove(&entity);
+                particle_system_render.update_bind_group.remove(&entity);
+            }
+        } else {
+            if !particle_system_render.sdf_textures.contains_key(&entity) {
+                particle_system_render
+                    .sdf_textures
+                    .insert(entity, create_sdf_texture(&render_device));
+                // obstacles were added after the update bind group was built without them
+                particle_system_render.update_bind_group.remove(&entity);
+            }
+
+            match bound_image(system.obstacles.mask.as_ref(), &gpu_images, &fallback_image) {
+                Some((bound, mask)) => {
+                    let stale = particle_system_render
+                        .obstacle_bind_groups
+                        .get(&entity)
+                        .map_or(true, |(built_with, _)| *built_with != bound);
+                    if stale {
+                        let obstacle_group = obstacle_bind_group(
+                            entity,
+                            &render_device,
+                            &obstacle_pipeline,
+                            &particle_system_render,
+                            mask,
+                        );
+                        particle_system_render
+                            .obstacle_bind_groups
+                            .insert(entity, (bound, obstacle_group));
+                    }
+                }
+                // the old mask is gone, nothing is baked until the new one loads
+                None => {
+                    particle_system_render.obstacle_bind_groups.remove(&entity);
+                }
+            }
+        }
+
+        if particle_system_render.grids.contains_key(&entity)
+            && !particle_system_render
+                .grid_bind_groups
+                .contains_key(&entity)
+        {
+            let grid_groups = [0, 1].map(|index| {
+                grid_bind_group(
+                    entity,
+                    &render_device,
+                    &grid_pipeline,
+                    &particle_system_render,
+                    index,
+                )
+            });
+            particle_system_render
+                .grid_bind_groups
+                .insert(entity, grid_groups);
+        }
+
+        // a spawn mask has to be loaded before the bind group can be built, and only physarum
+        // agents read the rendered texture
+        let mask = bound_image(system.emitter.mask(), &gpu_images, &fallback_image);
+        let trail = match system.behavior {
+            Behavior::Physarum(_) => {
+                bound_image(Some(&system.rendered_texture), &gpu_images, &fallback_image)
+            }
+            _ => bound_image(None, &gpu_images, &fallback_image),
+        };
+        let images = mask.as_ref().zip(trail.as_ref());
+        let bound = images.map(|((mask, _), (trail, _))| [mask.clone(), trail.clone()]);
+        if particle_system_render.update_bind_group_images.get(&entity) != bound.as_ref() {
+            particle_system_render.update_bind_group.remove(&entity);
+        }
+
         if !particle_system_render
             .update_bind_group
             .contains_key(&entity)
         {
-            let update_group = update_bind_group(
-                entity,
-                &render_device,
-                &update_pipeline,
-                &particle_system_render,
-            );
+            // without obstacles the sdf is never read, any texture will do
+            let obstacle_sdf = match particle_system_render.sdf_textures.get(&entity) {
+                Some(texture) => texture.create_view(&TextureViewDescriptor::default()),
+                None => fallback_image.texture_view.clone(),
+            };
+
+            if let Some(((_, mask), (_, trail))) = images {
+                let update_groups = [0, 1].map(|index| {
+                    update_bind_group(
+                        entity,
+                        &render_device,
+                        &update_pipeline,
+                        &particle_system_render,
+                        mask,
+                        &obstacle_sdf,
+                        trail,
+                        index,
+                    )
+                });
+                particle_system_render
+                    .update_bind_group
+                    .insert(entity, update_groups);
+            }
+        }
+        if let Some(bound) = bound {
             particle_system_render
-                .update_bind_group
-                .insert(entity, update_group);
+                .update_bind_group_images
+                .insert(entity, bound);
         }
 
-        if !particle_system_render
-            .render_bind_group
-            .contains_key(&entity)
+        if let Some(constraints) = &system.constraints {
+            if !particle_system_render.constraints.contains_key(&entity) {
+                let buffers = ConstraintBuffers::new(&render_device, constraints, system.capacity);
+                particle_system_render.constraints.insert(entity, buffers);
+            }
+
+            if !particle_system_render
+                .constraint_bind_groups
+                .contains_key(&entity)
+            {
+                let constraint_groups = [0, 1].map(|index| {
+                    constraint_bind_groups(
+                        entity,
+                        &render_device,
+                        &constraint_pipeline,
+                        &particle_system_render,
+                        index,
+                    )
+                });
+                particle_system_render
+                    .constraint_bind_groups
+                    .insert(entity, constraint_groups);
+                particle_system_render.unplaced_constraints.insert(entity);
+            }
+        }
+
+        if let Behavior::NBody(_) = system.behavior {
+            if !particle_system_render
+                .nbody_bind_groups
+                .contains_key(&entity)
+            {
+                let nbody_groups = [0, 1].map(|index| {
+                    nbody_bind_group(
+                        entity,
+                        &render_device,
+                        &nbody_pipeline,
+                        &particle_system_render,
+                        index,
+                    )
+                });
+                particle_system_render
+                    .nbody_bind_groups
+                    .insert(entity, nbody_groups);
+            }
+        } else if particle_system_render
+            .nbody_bind_groups
+            .remove(&entity)
+            .is_some()
         {
-            let view = &gpu_images[&system.rendered_texture];
-            let render_group = render_bind_group(
-                entity,
-                &render_device,
-                &render_pipeline,
-                &particle_system_render,
-                view,
-            );
+            // the update node runs gravity for as long as the bind groups are around
+            particle_system_render.clear_interaction_forces(entity, &render_queue);
+        }
+
+        if let Behavior::Physarum(_) = system.behavior {
+            if !particle_system_render.trail_textures.contains_key(&entity) {
+                particle_system_render
+                    .trail_textures
+                    .insert(entity, create_trail_texture(&render_device));
+            }
+
+            if !particle_system_render
+                .trail_bind_groups
+                .contains_key(&entity)
+            {
+                if let Some(texture) = gpu_images.get(&system.rendered_texture) {
+                    let trail_group = trail_bind_group(
+                        entity,
+                        &render_device,
+                        &render_pipeline,
+                        &particle_system_render,
+                        texture,
+                    );
+                    particle_system_render
+                        .trail_bind_groups
+                        .insert(entity, trail_group);
+                }
+            }
+        }
 
+        if system.render_mode == RenderMode::Quads
+            && !particle_system_render
+                .quads_bind_groups
+                .contains_key(&entity)
+        {
+            let quads_groups = [0, 1].map(|index| {
+                quads_bind_group(
+                    entity,
+                    &render_device,
+                    &quads_pipeline,
+                    &particle_system_render,
+                    index,
+                )
+            });
             particle_system_render
+                .quads_bind_groups
+                .insert(entity, quads_groups);
+        }
+
+        if let Some(flipbook) = flipbooks.0.get(&entity) {
+            // a new image or frames need new frames and a new bind group
+            let changed = particle_system_render
+                .flipbook_frames
+                .get(&entity)
+                .map_or(true, |frames| frames.sheet != *flipbook);
+            if changed {
+                particle_system_render
+                    .flipbook_frames
+                    .insert(entity, create_flipbook_frames(&render_device, flipbook));
+                particle_system_render.flipbook_bind_groups.remove(&entity);
+            }
+
+            if let Some(image) = gpu_images.get(&flipbook.image) {
+                if !particle_system_render
+                    .flipbook_bind_groups
+                    .contains_key(&entity)
+                {
+                    let flipbook_group = flipbook_bind_group(
+                        &render_device,
+                        &quads_pipeline,
+                        image,
+                        &particle_system_render.flipbook_frames[&entity].buffer,
+                    );
+                    particle_system_render
+                        .flipbook_bind_groups
+                        .insert(entity, flipbook_group);
+                }
+            }
+        } else {
+            particle_system_render.flipbook_frames.remove(&entity);
+            particle_system_render.flipbook_bind_groups.remove(&entity);
+        }
+
+        // only texture systems blend through the accumulation buffer, switching the render
+        // mode rebinds it
+        let accumulates = accumulates(system);
+        if accumulates
+            != particle_system_render
+                .accumulation_buffers
+                .contains_key(&entity)
+        {
+            if accumulates {
+                particle_system_render
+                    .accumulation_buffers
+                    .insert(entity, create_accumulation_buffer(&render_device));
+            } else {
+                particle_system_render.accumulation_buffers.remove(&entity);
+            }
+            particle_system_render.render_bind_group.remove(&entity);
+        }
+
+        // quads systems get by without a texture, the others wait for theirs to load
+        if plots(system)
+            && !particle_system_render
                 .render_bind_group
-                .insert(entity, render_group);
+                .contains_key(&entity)
+        {
+            if let Some(view) = gpu_images.get(&system.rendered_texture) {
+                let render_groups = [0, 1].map(|index| {
+                    render_bind_group(
+                        entity,
+                        &render_device,
+                        &render_pipeline,
+                        &particle_system_render,
+                        view,
+                        index,
+                    )
+                });
+                particle_system_render
+                    .render_bind_group
+                    .insert(entity, render_groups);
+            }
         }
     }
 }
@@ -139,3 +847,65 @@ impl ExtractComponent for ParticleSystem {
         item.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestep(max_steps: u32) -> ParticleTimestep {
+        ParticleTimestep {
+            step_seconds: 0.25,
+            max_steps,
+        }
+    }
+
+    #[test]
+    fn advance_runs_the_steps_that_fit() {
+        let mut time = SimulationTime::default();
+        time.advance(0.5, &timestep(4));
+        assert_eq!(time.steps, 2);
+        assert_eq!(time.delta_seconds, 0.25);
+        assert_eq!(time.elapsed_seconds, 0.5);
+        assert_eq!(time.alpha, 0.0);
+    }
+
+    #[test]
+    fn advance_drops_steps_beyond_the_max() {
+        let mut time = SimulationTime::default();
+        time.advance(2.0, &timestep(4));
+        assert_eq!(time.steps, 4);
+        assert_eq!(time.elapsed_seconds, 1.0);
+
+        time.advance(0.25, &timestep(4));
+        assert_eq!(time.steps, 1);
+        assert_eq!(time.elapsed_seconds, 1.25);
+    }
+
+    #[test]
+    fn advance_carries_the_remainder_over() {
+        let mut time = SimulationTime::default();
+        time.advance(0.375, &timestep(4));
+        assert_eq!(time.steps, 1);
+        assert_eq!(time.alpha, 0.5);
+
+        time.advance(0.375, &timestep(4));
+        assert_eq!(time.steps, 2);
+        assert_eq!(time.elapsed_seconds, 0.75);
+        assert_eq!(time.alpha, 0.0);
+    }
+
+    #[test]
+    fn advance_pauses_without_a_step_length() {
+        let mut time = SimulationTime::default();
+        time.advance(1.0, &timestep(4));
+        time.advance(
+            1.0,
+            &ParticleTimestep {
+                step_seconds: 0.0,
+                max_steps: 4,
+            },
+        );
+        assert_eq!(time.steps, 0);
+        assert_eq!(time.elapsed_seconds, 1.0);
+    }
+}
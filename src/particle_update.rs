@@ -4,13 +4,22 @@ use bevy::{
         render_graph::{self},
         render_resource::*,
         renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
     },
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 
 use crate::{
-    compute_utils::{compute_pipeline_descriptor, run_compute_pass},
-    particle_system::ParticleSystemRender,
+    behavior::Behavior,
+    compute_utils::{
+        compute_pipeline_descriptor, run_compute_pass, run_compute_pass_2d, run_tiled_compute_pass,
+    },
+    constraint::ConstraintPipeline,
+    nbody::NBodyPipeline,
+    obstacle::ObstaclePipeline,
+    particle::{EmitterUniform, ForceFields, SimulationUniform},
+    particle_system::{ParticleSystemRender, SimulationTime},
+    spatial_grid::{run_grid_passes, SpatialGridPipeline},
     ParticleSystem,
 };
 
@@ -18,12 +27,17 @@ use crate::{
 pub struct ParticleUpdatePipeline {
     bind_group_layout: BindGroupLayout,
     init_pipeline: CachedComputePipelineId,
+    emit_pipeline: CachedComputePipelineId,
     update_pipeline: CachedComputePipelineId,
+    boids_pipeline: CachedComputePipelineId,
+    physarum_pipeline: CachedComputePipelineId,
 }
 
 pub struct UpdateParticlesNode {
-    particle_systems: QueryState<Entity, With<ParticleSystem>>,
+    particle_systems: QueryState<(Entity, &'static ParticleSystem)>,
     update_state: HashMap<Entity, ParticleUpdateState>,
+    /// Systems `run` places the constraint points of this frame.
+    placing: HashSet<Entity>,
 }
 
 #[derive(Default, Clone)]
@@ -37,34 +51,183 @@ enum ParticleUpdateState {
 fn update_bind_group_layout() -> BindGroupLayoutDescriptor<'static> {
     BindGroupLayoutDescriptor {
         label: None,
-        entries: &[BindGroupLayoutEntry {
-            binding: 0,
-            visibility: ShaderStages::COMPUTE,
-            ty: BindingType::Buffer {
-                ty: BufferBindingType::Storage { read_only: false },
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            },
-            count: None,
-        }],
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(SimulationUniform::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(EmitterUniform::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(ForceFields::min_size()),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 6,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 7,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 8,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: false },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 9,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
     }
 }
 
+/// Writes particle buffer `index`, update steps on from the other one.
 pub fn update_bind_group(
     entity: Entity,
     render_device: &RenderDevice,
     update_pipeline: &ParticleUpdatePipeline,
     particle_system_render: &ParticleSystemRender,
+    mask: &GpuImage,
+    obstacle_sdf: &TextureView,
+    trail: &GpuImage,
+    index: usize,
 ) -> BindGroup {
+    let particle_buffers = &particle_system_render.particle_buffers[&entity];
+
     render_device.create_bind_group(&BindGroupDescriptor {
         label: None,
         layout: &update_pipeline.bind_group_layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::Buffer(
-                particle_system_render.particle_buffers[&entity].as_entire_buffer_binding(),
-            ),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(
+                    particle_buffers[index].as_entire_buffer_binding(),
+                ),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: particle_system_render.simulation_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Buffer(
+                    particle_system_render.dead_list_buffers[&entity].as_entire_buffer_binding(),
+                ),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: particle_system_render.emitter_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: BindingResource::TextureView(&mask.texture_view),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: particle_system_render.force_fields[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: BindingResource::TextureView(obstacle_sdf),
+            },
+            BindGroupEntry {
+                binding: 7,
+                resource: BindingResource::Buffer(
+                    particle_system_render.interaction_buffers[&entity].as_entire_buffer_binding(),
+                ),
+            },
+            BindGroupEntry {
+                binding: 8,
+                resource: BindingResource::TextureView(&trail.texture_view),
+            },
+            BindGroupEntry {
+                binding: 9,
+                resource: BindingResource::Buffer(
+                    particle_buffers[1 - index].as_entire_buffer_binding(),
+                ),
+            },
+        ],
     })
 }
 
@@ -74,7 +237,9 @@ impl FromWorld for ParticleUpdatePipeline {
             .resource::<RenderDevice>()
             .create_bind_group_layout(&update_bind_group_layout());
 
-        let shader = world.resource::<AssetServer>().load("particle_update.wgsl");
+        let shader = world
+            .resource::<AssetServer>()
+            .load("particle_update.compute.wgsl");
 
         let mut pipeline_cache = world.resource_mut::<PipelineCache>();
 
@@ -82,31 +247,132 @@ impl FromWorld for ParticleUpdatePipeline {
             shader.clone(),
             "init",
             &bind_group_layout,
+            vec![],
+        ));
+
+        let emit_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader.clone(),
+            "emit",
+            &bind_group_layout,
+            vec![],
         ));
 
         let update_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader.clone(),
+            "update",
+            &bind_group_layout,
+            vec![],
+        ));
+
+        let boids_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+            shader.clone(),
+            "update",
+            &bind_group_layout,
+            vec!["BOIDS".to_string()],
+        ));
+
+        let physarum_pipeline = pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
             shader,
             "update",
             &bind_group_layout,
+            vec!["PHYSARUM".to_string()],
         ));
 
         ParticleUpdatePipeline {
             bind_group_layout,
             init_pipeline,
+            emit_pipeline,
             update_pipeline,
+            boids_pipeline,
+            physarum_pipeline,
         }
     }
 }
 
 impl render_graph::Node for UpdateParticlesNode {
     fn update(&mut self, world: &mut World) {
+        let reallocated: Vec<_> = world
+            .resource_mut::<ParticleSystemRender>()
+            .reallocated
+            .drain()
+            .collect();
+        for entity in reallocated {
+            // fresh buffers have to go through the init pass again
+            self.update_state.remove(&entity);
+        }
+
         let mut systems = world.query_filtered::<Entity, With<ParticleSystem>>();
         let pipeline = world.resource::<ParticleUpdatePipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
+        let particle_systems_render = world.resource::<ParticleSystemRender>();
+        // the other passes this node runs during the update state
+        let mut dependencies = vec![world.resource::<ObstaclePipeline>().bake_pipeline];
+        dependencies.extend(world.resource::<SpatialGridPipeline>().pipelines());
+        dependencies.push(world.resource::<NBodyPipeline>().gravity_pipeline);
+        let constraint_pipeline = world.resource::<ConstraintPipeline>();
+        dependencies.push(constraint_pipeline.place_pipeline);
+        dependencies.push(constraint_pipeline.solve_pipeline);
 
         for entity in systems.iter(world) {
+            // systems wait in place until their bind group could be built
+            if !particle_systems_render
+                .update_bind_group
+                .contains_key(&entity)
+            {
+                continue;
+            }
             // if the corresponding pipeline has loaded, transition to the next stage
-            self.update_state(entity, pipeline_cache, pipeline);
+            self.update_state(entity, pipeline_cache, pipeline, &dependencies);
+        }
+
+        // init clears the points again, so they are placed on every init frame and once more
+        // whenever the bind groups are rebuilt later on
+        let place_ready = matches!(
+            pipeline_cache.get_compute_pipeline_state(constraint_pipeline.place_pipeline),
+            CachedPipelineState::Ok(_)
+        );
+        self.placing.clear();
+        for (entity, state) in &self.update_state {
+            let place = match state {
+                ParticleUpdateState::Loading => false,
+                ParticleUpdateState::Init => true,
+                ParticleUpdateState::Update => particle_systems_render
+                    .unplaced_constraints
+                    .contains(entity),
+            };
+            let bound = particle_systems_render
+                .update_bind_group
+                .contains_key(entity)
+                && particle_systems_render
+                    .constraint_bind_groups
+                    .contains_key(entity);
+            if place_ready && place && bound {
+                self.placing.insert(*entity);
+            }
+        }
+
+        // every step `run` takes swaps the particle buffers, rendering picks up where they end
+        let steps = world.resource::<SimulationTime>().steps;
+        let mut particle_systems_render = world.resource_mut::<ParticleSystemRender>();
+        for entity in &self.placing {
+            particle_systems_render.unplaced_constraints.remove(entity);
+        }
+        for (entity, state) in &self.update_state {
+            // `run` skips systems without a bind group, their buffers and emits stay put
+            if !particle_systems_render
+                .update_bind_group
+                .contains_key(entity)
+            {
+                continue;
+            }
+            if let ParticleUpdateState::Update = state {
+                *particle_systems_render
+                    .current_buffers
+                    .entry(*entity)
+                    .or_default() ^= steps as usize % 2;
+                // `run` emits everything that piled up
+                particle_systems_render.pending_emits.remove(entity);
+            }
         }
         //Update the query for the run step
         self.particle_systems.update_archetypes(world);
@@ -120,21 +386,135 @@ impl render_graph::Node for UpdateParticlesNode {
     ) -> Result<(), render_graph::NodeRunError> {
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<ParticleUpdatePipeline>();
+        let obstacle_pipeline = world.resource::<ObstaclePipeline>();
+        let grid_pipeline = world.resource::<SpatialGridPipeline>();
+        let nbody_pipeline = world.resource::<NBodyPipeline>();
+        let constraint_pipeline = world.resource::<ConstraintPipeline>();
         let particle_systems_render = world.resource::<ParticleSystemRender>();
 
-        for entity in self.particle_systems.iter_manual(world) {
-            // select the pipeline based on the current state
-            if let Some(pipeline) = match self.update_state[&entity] {
-                ParticleUpdateState::Loading => None,
-                ParticleUpdateState::Init => Some(pipeline.init_pipeline),
-                ParticleUpdateState::Update => Some(pipeline.update_pipeline),
-            } {
-                run_compute_pass(
-                    render_context,
-                    &particle_systems_render.update_bind_group[&entity],
-                    pipeline_cache,
-                    pipeline,
-                );
+        let time = world.resource::<SimulationTime>();
+
+        for (entity, system) in self.particle_systems.iter_manual(world) {
+            let Some(bind_groups) = particle_systems_render.update_bind_group.get(&entity) else {
+                continue;
+            };
+            let mut current = particle_systems_render.current_buffer(entity);
+            // select the pipelines based on the current state
+            match self.update_state[&entity] {
+                ParticleUpdateState::Loading => {}
+                ParticleUpdateState::Init => {
+                    run_compute_pass(
+                        render_context,
+                        &bind_groups[current],
+                        pipeline_cache,
+                        pipeline.init_pipeline,
+                        system.capacity,
+                    );
+                    if self.placing.contains(&entity) {
+                        run_place_pass(
+                            render_context,
+                            particle_systems_render,
+                            pipeline_cache,
+                            constraint_pipeline,
+                            entity,
+                            current,
+                        );
+                    }
+                }
+                ParticleUpdateState::Update => {
+                    // back to the buffer holding the state from before this frame's steps
+                    current ^= time.steps as usize % 2;
+                    if self.placing.contains(&entity) {
+                        run_place_pass(
+                            render_context,
+                            particle_systems_render,
+                            pipeline_cache,
+                            constraint_pipeline,
+                            entity,
+                            current,
+                        );
+                    }
+                    // obstacles may move, so their sdf is baked again every frame
+                    if let Some((_, obstacle_group)) =
+                        particle_systems_render.obstacle_bind_groups.get(&entity)
+                    {
+                        run_compute_pass_2d(
+                            render_context,
+                            obstacle_group,
+                            pipeline_cache,
+                            obstacle_pipeline.bake_pipeline,
+                        );
+                    }
+                    let emit_count = particle_systems_render.simulation_uniforms[&entity]
+                        .get()
+                        .emit_count;
+                    run_compute_pass(
+                        render_context,
+                        &bind_groups[current],
+                        pipeline_cache,
+                        pipeline.emit_pipeline,
+                        emit_count,
+                    );
+                    let grid = particle_systems_render
+                        .grids
+                        .get(&entity)
+                        .zip(particle_systems_render.grid_bind_groups.get(&entity));
+                    let nbody_groups = particle_systems_render.nbody_bind_groups.get(&entity);
+                    let constraint_groups = particle_systems_render
+                        .constraint_bind_groups
+                        .get(&entity)
+                        .zip(system.constraints.as_ref());
+                    let update_pipeline = match system.behavior {
+                        Behavior::Boids(_) => pipeline.boids_pipeline,
+                        Behavior::Physarum(_) => pipeline.physarum_pipeline,
+                        _ => pipeline.update_pipeline,
+                    };
+                    for _ in 0..time.steps {
+                        // neighbors are read from the current state, update writes the next
+                        let next = 1 - current;
+                        if let Some((grid, grid_groups)) = grid {
+                            run_grid_passes(
+                                render_context,
+                                &grid_groups[current],
+                                pipeline_cache,
+                                grid_pipeline,
+                                grid,
+                                &system.behavior,
+                                system.capacity,
+                            );
+                        }
+                        if let Some(nbody_groups) = nbody_groups {
+                            run_tiled_compute_pass(
+                                render_context,
+                                &nbody_groups[current],
+                                pipeline_cache,
+                                nbody_pipeline.gravity_pipeline,
+                                system.capacity,
+                            );
+                        }
+                        run_compute_pass(
+                            render_context,
+                            &bind_groups[next],
+                            pipeline_cache,
+                            update_pipeline,
+                            system.capacity,
+                        );
+                        if let Some((constraint_groups, constraints)) = constraint_groups {
+                            for _ in 0..constraints.iterations {
+                                for (constraint_group, links) in &constraint_groups[next] {
+                                    run_compute_pass(
+                                        render_context,
+                                        constraint_group,
+                                        pipeline_cache,
+                                        constraint_pipeline.solve_pipeline,
+                                        *links,
+                                    );
+                                }
+                            }
+                        }
+                        current = next;
+                    }
+                }
             }
         }
 
@@ -142,11 +522,33 @@ impl render_graph::Node for UpdateParticlesNode {
     }
 }
 
+/// Puts a system's constraint points into particle buffer `index`.
+fn run_place_pass(
+    render_context: &mut RenderContext,
+    particle_systems_render: &ParticleSystemRender,
+    pipeline_cache: &PipelineCache,
+    constraint_pipeline: &ConstraintPipeline,
+    entity: Entity,
+    index: usize,
+) {
+    let points = particle_systems_render.simulation_uniforms[&entity]
+        .get()
+        .constraint_points;
+    run_compute_pass(
+        render_context,
+        &particle_systems_render.constraint_bind_groups[&entity][index][0].0,
+        pipeline_cache,
+        constraint_pipeline.place_pipeline,
+        points,
+    );
+}
+
 impl UpdateParticlesNode {
     pub fn new(world: &mut World) -> Self {
         Self {
             particle_systems: QueryState::new(world),
             update_state: HashMap::default(),
+            placing: HashSet::default(),
         }
     }
 
@@ -155,6 +557,7 @@ impl UpdateParticlesNode {
         entity: Entity,
         pipeline_cache: &PipelineCache,
         pipeline: &ParticleUpdatePipeline,
+        dependencies: &[CachedComputePipelineId],
     ) {
         let update_state = match self.update_state.get(&entity) {
             Some(state) => state,
@@ -174,9 +577,21 @@ impl UpdateParticlesNode {
                 }
             }
             ParticleUpdateState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
-                {
+                let ready = [
+                    pipeline.emit_pipeline,
+                    pipeline.update_pipeline,
+                    pipeline.boids_pipeline,
+                    pipeline.physarum_pipeline,
+                ]
+                .iter()
+                .chain(dependencies)
+                .all(|id| {
+                    matches!(
+                        pipeline_cache.get_compute_pipeline_state(*id),
+                        CachedPipelineState::Ok(_)
+                    )
+                });
+                if ready {
                     self.update_state
                         .insert(entity, ParticleUpdateState::Update);
                 }
@@ -0,0 +1,269 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+    },
+};
+
+use crate::{
+    behavior::Behavior,
+    compute_utils::{compute_pipeline_descriptor, run_compute_pass, storage_entry},
+    particle::SimulationUniform,
+    particle_system::ParticleSystemRender,
+    HEIGHT, WIDTH,
+};
+
+/// Forces between nearby particles. Neighbors are found through a grid over the rendered
+/// texture that is rebuilt every step, so this scales with the particle count instead of
+/// its square. Boids and fluids bring their own neighbor forces and ignore this.
+#[derive(Reflect, FromReflect, Clone, Debug)]
+pub struct Interaction {
+    /// Furthest particles affect each other, in texture pixels. Changing it rebuilds the grid
+    /// with cells of the new size.
+    pub radius: f32,
+    /// Acceleration pushing particles apart, strongest when they overlap.
+    pub separation: f32,
+}
+
+impl Default for Interaction {
+    fn default() -> Self {
+        Self {
+            radius: 4.0,
+            separation: 200.0,
+        }
+    }
+}
+
+/// Smallest cell size, keeps the cell count of tiny radii in check.
+const MIN_CELL_SIZE: f32 = 2.0;
+
+/// Gpu side of a system's neighbor grid, particles are counting sorted by cell every step.
+pub struct SpatialGrid {
+    pub cells: UVec2,
+    pub cell_size: f32,
+    capacity: u32,
+    /// Particles per cell, reused as the write cursor of each cell while scattering.
+    cell_counts: Buffer,
+    /// Where each cell starts in `sorted_indices`, with one extra entry for the end.
+    cell_starts: Buffer,
+    sorted_indices: Buffer,
+    /// Density and pressure of every particle, only used by fluids.
+    densities: Buffer,
+}
+
+impl SpatialGrid {
+    /// Lays out cells of at least `radius`, so neighbors are always in adjacent cells.
+    pub fn new(render_device: &RenderDevice, radius: f32, capacity: u32) -> Self {
+        let cell_size = radius.max(MIN_CELL_SIZE);
+        let cells = UVec2::new(
+            (WIDTH / cell_size).ceil() as u32,
+            (HEIGHT / cell_size).ceil() as u32,
+        );
+        let cell_count = (cells.x * cells.y) as u64;
+
+        let buffer = |size: u64| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: None,
+                size,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+
+        Self {
+            cells,
+            cell_size,
+            capacity,
+            cell_counts: buffer(4 * cell_count),
+            cell_starts: buffer(4 * (cell_count + 1)),
+            sorted_indices: buffer(4 * capacity as u64),
+            densities: buffer(8 * capacity as u64),
+        }
+    }
+
+    /// Whether `new` would lay out the same grid, otherwise it has to be built again.
+    pub fn fits(&self, radius: f32, capacity: u32) -> bool {
+        self.cell_size == radius.max(MIN_CELL_SIZE) && self.capacity == capacity
+    }
+
+    pub fn cell_count(&self) -> u32 {
+        self.cells.x * self.cells.y
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct SpatialGridPipeline {
+    bind_group_layout: BindGroupLayout,
+    clear_pipeline: CachedComputePipelineId,
+    count_pipeline: CachedComputePipelineId,
+    prefix_sum_pipeline: CachedComputePipelineId,
+    scatter_pipeline: CachedComputePipelineId,
+    interact_pipeline: CachedComputePipelineId,
+    flock_pipeline: CachedComputePipelineId,
+    density_pipeline: CachedComputePipelineId,
+    pressure_pipeline: CachedComputePipelineId,
+    viscosity_pipeline: CachedComputePipelineId,
+}
+
+/// Reads neighbors from particle buffer `index`.
+pub fn grid_bind_group(
+    entity: Entity,
+    render_device: &RenderDevice,
+    grid_pipeline: &SpatialGridPipeline,
+    particle_system_render: &ParticleSystemRender,
+    index: usize,
+) -> BindGroup {
+    let grid = &particle_system_render.grids[&entity];
+    let buffer = |buffer: &Buffer| BindingResource::Buffer(buffer.as_entire_buffer_binding());
+
+    render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &grid_pipeline.bind_group_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: buffer(&particle_system_render.particle_buffers[&entity][index]),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: particle_system_render.simulation_uniforms[&entity]
+                    .binding()
+                    .unwrap(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: buffer(&grid.cell_counts),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: buffer(&grid.cell_starts),
+            },
+            BindGroupEntry {
+                binding: 4,
+                resource: buffer(&grid.sorted_indices),
+            },
+            BindGroupEntry {
+                binding: 5,
+                resource: buffer(&particle_system_render.interaction_buffers[&entity]),
+            },
+            BindGroupEntry {
+                binding: 6,
+                resource: buffer(&grid.densities),
+            },
+        ],
+    })
+}
+
+impl FromWorld for SpatialGridPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let bind_group_layout =
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries: &[
+                        storage_entry(0, true),
+                        BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: ShaderStages::COMPUTE,
+                            ty: BindingType::Buffer {
+                                ty: BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(SimulationUniform::min_size()),
+                            },
+                            count: None,
+                        },
+                        storage_entry(2, false),
+                        storage_entry(3, false),
+                        storage_entry(4, false),
+                        storage_entry(5, false),
+                        storage_entry(6, false),
+                    ],
+                });
+        let shader = world
+            .resource::<AssetServer>()
+            .load("spatial_grid.compute.wgsl");
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+
+        let mut queue = |entry_point| {
+            pipeline_cache.queue_compute_pipeline(compute_pipeline_descriptor(
+                shader.clone(),
+                entry_point,
+                &bind_group_layout,
+                vec![],
+            ))
+        };
+
+        SpatialGridPipeline {
+            clear_pipeline: queue("clear_cells"),
+            count_pipeline: queue("count"),
+            prefix_sum_pipeline: queue("prefix_sum"),
+            scatter_pipeline: queue("scatter"),
+            interact_pipeline: queue("interact"),
+            flock_pipeline: queue("flock"),
+            density_pipeline: queue("density"),
+            pressure_pipeline: queue("pressure"),
+            viscosity_pipeline: queue("viscosity"),
+            bind_group_layout,
+        }
+    }
+}
+
+impl SpatialGridPipeline {
+    pub fn pipelines(&self) -> [CachedComputePipelineId; 9] {
+        [
+            self.clear_pipeline,
+            self.count_pipeline,
+            self.prefix_sum_pipeline,
+            self.scatter_pipeline,
+            self.interact_pipeline,
+            self.flock_pipeline,
+            self.density_pipeline,
+            self.pressure_pipeline,
+            self.viscosity_pipeline,
+        ]
+    }
+
+    /// The passes summing up the forces between neighbors, in order.
+    fn neighbor_pipelines(&self, behavior: &Behavior) -> Vec<CachedComputePipelineId> {
+        match behavior {
+            Behavior::Ballistic | Behavior::Physarum(_) | Behavior::NBody(_) => {
+                vec![self.interact_pipeline]
+            }
+            Behavior::Boids(_) => vec![self.flock_pipeline],
+            // forces need the density of every neighbor, so it gets a pass of its own
+            Behavior::Fluid(_) => vec![
+                self.density_pipeline,
+                self.pressure_pipeline,
+                self.viscosity_pipeline,
+            ],
+        }
+    }
+}
+
+/// Sorts the particles into the grid, then sums up the forces between neighbors.
+pub fn run_grid_passes(
+    render_context: &mut RenderContext,
+    bind_group: &BindGroup,
+    pipeline_cache: &PipelineCache,
+    grid_pipeline: &SpatialGridPipeline,
+    grid: &SpatialGrid,
+    behavior: &Behavior,
+    capacity: u32,
+) {
+    let passes = [
+        (grid_pipeline.clear_pipeline, grid.cell_count()),
+        (grid_pipeline.count_pipeline, capacity),
+        // a single workgroup scans all cells
+        (grid_pipeline.prefix_sum_pipeline, 1),
+        (grid_pipeline.scatter_pipeline, capacity),
+    ];
+    let neighbor_passes = grid_pipeline
+        .neighbor_pipelines(behavior)
+        .into_iter()
+        .map(|pipeline| (pipeline, capacity));
+    for (pipeline, count) in passes.into_iter().chain(neighbor_passes) {
+        run_compute_pass(render_context, bind_group, pipeline_cache, pipeline, count);
+    }
+}